@@ -1,36 +1,242 @@
-use std::path::PathBuf;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+/// A shared library of the sysroot, under all of the names it goes by
+#[derive(Clone)]
+struct Library {
+    /// The files which are the library, links to it included, the preferred name first
+    paths: Vec<PathBuf>,
+}
+
 fn main(){
-    let (path, lib) = find_llvm_lib();
-    println!("cargo:rustc-link-search=native={}", path.display());
-    println!("cargo:rustc-link-lib=dylib={}", lib);
-}
-
-fn find_llvm_lib() -> (PathBuf, String) {
-    // TODO: cargo does not export us a $RUSTC
-    let sysroot = Command::new("rustc").arg("--print=sysroot").output()
-        .expect("could not execute rustc");
-    // FIXME: may be not utf8?
-    let mut path = String::from_utf8(sysroot.stdout).expect("rustc output not utf-8");
-    while let Some(ch) = path.pop() {
-        if !ch.is_whitespace() {
-            path.push(ch);
-            break;
+    if env::var_os("CARGO_FEATURE_SYSTEM_LLVM").is_some() {
+        return link_system_llvm();
+    }
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rerun-if-env-changed=RUSTC_WRAPPER");
+    let version = rustc_version();
+    let host = version.iter().filter_map(|l| l.split("host: ").nth(1)).next().unwrap_or("");
+    for library in find_llvm_libs(host) {
+        let path = &library.paths[0];
+        println!("cargo:rustc-link-search=native={}", path.parent().unwrap().display());
+        println!("cargo:rustc-link-lib={}", link_lib(path));
+    }
+    println!("cargo:rustc-env=LLVM_BUILD_UTILS_RUSTC_VERSION={}", version.join("; "));
+}
+
+/// Link to the shared LLVM library of the `llvm-config` found by `find_llvm_config`
+fn link_system_llvm() {
+    let llvm_config = find_llvm_config();
+    let run = |args: &[&str]| {
+        let mut command = Command::new(&llvm_config);
+        command.args(args);
+        let output = command.output().unwrap_or_else(|e|
+            panic!("could not execute {:?}: {}; set LLVM_CONFIG_PATH to the path of llvm-config",
+                   command, e));
+        if !output.status.success() {
+            panic!("{:?} failed with {}:\n{}", command, output.status,
+                   String::from_utf8_lossy(&output.stderr));
+        }
+        String::from(String::from_utf8_lossy(&output.stdout).trim())
+    };
+    if run(&["--shared-mode"]) != "shared" {
+        panic!("the LLVM of {} is not built as a shared library, which the system-llvm feature \
+                requires", llvm_config.display());
+    }
+    println!("cargo:rustc-link-search=native={}", run(&["--libdir"]));
+    for lib in run(&["--link-shared", "--libs"]).split_whitespace() {
+        println!("cargo:rustc-link-lib=dylib={}", lib.trim_start_matches("-l"));
+    }
+    println!("cargo:rustc-env=LLVM_BUILD_UTILS_RUSTC_VERSION=LLVM {} ({})",
+             run(&["--version"]), llvm_config.display());
+}
+
+/// The `llvm-config` named by `$LLVM_CONFIG_PATH`, the one of the newest LLVM with a
+/// `$LLVM_SYS_<version>_PREFIX`, or the first one in `$PATH`
+fn find_llvm_config() -> PathBuf {
+    println!("cargo:rerun-if-env-changed=LLVM_CONFIG_PATH");
+    println!("cargo:rerun-if-env-changed=PATH");
+    if let Some(path) = env::var_os("LLVM_CONFIG_PATH") {
+        return PathBuf::from(path);
+    }
+    let mut prefixes = env::vars_os().filter_map(|(key, value)| {
+        let key = key.to_string_lossy().into_owned();
+        if !key.starts_with("LLVM_SYS_") || !key.ends_with("_PREFIX") {
+            return None;
         }
+        println!("cargo:rerun-if-env-changed={}", key);
+        key["LLVM_SYS_".len()..key.len() - "_PREFIX".len()].parse::<u32>().ok()
+                                                          .map(|v| (v, PathBuf::from(value)))
+    }).collect::<Vec<_>>();
+    prefixes.sort();
+    match prefixes.pop() {
+        Some((_, prefix)) => prefix.join("bin").join("llvm-config"),
+        None => PathBuf::from("llvm-config"),
     }
-    let mut path = PathBuf::from(path);
-    path.push("lib");
-    for entry in path.read_dir().expect("could not read dir") {
-        let entry_path = entry.expect("could not read dir").path();
-        let entry_path = entry_path.with_extension("");
-        let fname = entry_path.file_name();
-        if let Some(Some(st)) = fname.map(|f| f.to_str()) {
-            if let Some(i) = st.find("rustc_llvm") {
-                return (path, String::from(&st[i..]));
+}
+
+/// The rustc cargo builds with, run through `$RUSTC_WRAPPER` like cargo does
+fn rustc() -> Command {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| OsString::from("rustc"));
+    match env::var_os("RUSTC_WRAPPER") {
+        Some(ref wrapper) if !wrapper.is_empty() => {
+            let mut command = Command::new(wrapper);
+            command.arg(rustc);
+            command
+        }
+        _ => Command::new(rustc),
+    }
+}
+
+/// Run rustc with the argument, returning its output
+fn run_rustc(arg: &str) -> Vec<u8> {
+    let mut command = rustc();
+    command.arg(arg);
+    let output = command.output().unwrap_or_else(|e|
+        panic!("could not execute {:?}: {}; set RUSTC to the path of rustc", command, e));
+    if !output.status.success() {
+        panic!("{:?} failed with {}:\n{}", command, output.status,
+               String::from_utf8_lossy(&output.stderr));
+    }
+    output.stdout
+}
+
+/// Verbose version of the rustc (and therefore LLVM) being linked to, one line per entry
+fn rustc_version() -> Vec<String> {
+    String::from_utf8_lossy(&run_rustc("-vV")).lines().map(String::from).collect()
+}
+
+fn sysroot() -> PathBuf {
+    let mut sysroot = run_rustc("--print=sysroot");
+    while sysroot.last().map(|b| b.is_ascii_whitespace()) == Some(true) {
+        sysroot.pop();
+    }
+    path_from_bytes(sysroot)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8(bytes).expect("rustc printed a sysroot which is not utf-8"))
+}
+
+/// Find the libraries of the sysroot providing LLVM and the `LLVMRust*` functions
+///
+/// The functions used to live in `rustc_llvm`, which became a part of `rustc_driver`. LLVM itself
+/// is either linked into these or, on some hosts, a separate `libLLVM` they link to. Whenever a
+/// sysroot has several candidates, e.g. left behind by an earlier toolchain, the one the
+/// compiler actually uses is picked.
+fn find_llvm_libs(host: &str) -> Vec<Library> {
+    let sysroot = sysroot();
+    let dirs = [sysroot.join("lib"), sysroot.join("lib/rustlib").join(host).join("lib")];
+    let libraries = libraries(&dirs);
+    let of_kind = |kind: &str| libraries.iter().filter(|l| {
+        l.paths.iter().any(|p| file_name(p).trim_start_matches("lib").starts_with(kind))
+    }).collect::<Vec<_>>();
+    let rustc = sysroot.join("bin").join(if host.contains("windows") { "rustc.exe" }
+                                         else { "rustc" });
+    // Historical toolchains shipped both, with the functions in `rustc_llvm`
+    let driver = match choose(of_kind("rustc_llvm-"), &rustc) {
+        Some(library) => library,
+        None => match choose(of_kind("rustc_driver-"), &rustc) {
+            Some(library) => library,
+            None => panic!("could not find the LLVM of rustc: none of {} contain a rustc_driver or \
+                            rustc_llvm library; set RUSTC to a rustc of a toolchain which ships \
+                            its libraries, such as one installed by rustup",
+                           dirs.iter().map(|d| format!("`{}`", d.display()))
+                               .collect::<Vec<_>>().join(", ")),
+        },
+    };
+    let llvm = choose(of_kind("LLVM"), &driver.paths[0]);
+    Some(driver).into_iter().chain(llvm).collect()
+}
+
+/// The shared libraries in the directories, the files linking to the same library grouped
+fn libraries(dirs: &[PathBuf]) -> Vec<Library> {
+    let mut ret: Vec<(PathBuf, Library)> = Vec::new();
+    for dir in dirs {
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut paths = entries.filter_map(|e| e.ok().map(|e| e.path()))
+                               .filter(|p| is_shared_library(&file_name(p)))
+                               .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            match ret.iter().position(|l| l.0 == canonical) {
+                Some(idx) => ret[idx].1.paths.push(path),
+                None => ret.push((canonical, Library { paths: vec![path] })),
             }
         }
     }
-    panic!("could not find rustc_llvm library");
+    for &mut (_, ref mut library) in &mut ret {
+        // Prefer the names which can be linked to without the `verbatim` modifier
+        library.paths.sort_by_key(|p| link_lib(p).contains("verbatim"));
+    }
+    ret.into_iter().map(|(_, library)| library).collect()
+}
+
+/// Whether the file is a shared library or, on Windows, the import library of one, of rustc
+fn is_shared_library(name: &str) -> bool {
+    let stem = name.trim_start_matches("lib");
+    if !["rustc_llvm-", "rustc_driver-", "LLVM"].iter().any(|k| stem.starts_with(k)) {
+        return false;
+    }
+    name.ends_with(".so") || name.contains(".so.") || name.ends_with(".dylib") ||
+    name.ends_with(".dll.a") || name.ends_with(".dll.lib")
+}
 
+/// Pick the library `referrer` links to, falling back to the most recently modified one
+fn choose(candidates: Vec<&Library>, referrer: &Path) -> Option<Library> {
+    let referenced = if candidates.len() > 1 {
+        let contents = fs::read(referrer).unwrap_or_default();
+        candidates.iter().cloned().filter(|l| l.paths.iter().any(|p| {
+            let name = loaded_name(p);
+            contents.windows(name.len()).any(|w| w == name.as_bytes())
+        })).collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+    let candidates = if referenced.len() == 1 { referenced } else { candidates };
+    candidates.into_iter().max_by_key(|l| {
+        let modified = fs::metadata(&l.paths[0]).and_then(|m| m.modified()).ok();
+        (modified, l.paths[0].clone())
+    }).cloned()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Name of the file the library is loaded from, which differs from the import library on Windows
+fn loaded_name(path: &Path) -> String {
+    let name = file_name(path);
+    if name.ends_with(".dll.lib") {
+        String::from(&name[..name.len() - 4])
+    } else if name.starts_with("lib") && name.ends_with(".dll.a") {
+        String::from(&name[3..name.len() - 2])
+    } else {
+        name
+    }
+}
+
+/// The `rustc-link-lib` directive linking to the library at the path
+fn link_lib(path: &Path) -> String {
+    let name = file_name(path);
+    let linkable = [".so", ".dylib", ".dll.a"].iter().find(|&&e| name.ends_with(e));
+    match linkable {
+        Some(ext) if name.starts_with("lib") =>
+            format!("dylib={}", &name[3..name.len() - ext.len()]),
+        _ => format!("dylib:+verbatim={}", name),
+    }
 }
@@ -0,0 +1,592 @@
+//! Archives and the symbols of the objects in them, for when LLVM is used through its C API
+//!
+//! The C API can neither read nor write archives, so the formats are handled here instead.
+use ArchiveKind;
+
+const MAGIC: &'static [u8] = b"!<arch>\n";
+const HEADER: usize = 60;
+
+/// Split an archive into its members, as `(name, contents)` pairs
+///
+/// Symbol tables and the table of long names are not members.
+pub fn read(archive: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, &'static str> {
+    if archive.starts_with(b"!<thin>\n") {
+        return Err("thin archives are not supported");
+    }
+    if !archive.starts_with(MAGIC) {
+        return Err("not an archive");
+    }
+    let mut members = Vec::new();
+    let mut long_names: &[u8] = &[];
+    let mut offset = MAGIC.len();
+    // Members are aligned to two bytes, the archive may end in padding
+    while archive.len() - offset > 1 {
+        let header = archive.get(offset..offset + HEADER).ok_or("truncated member header")?;
+        if &header[58..] != b"`\n" {
+            return Err("malformed member header");
+        }
+        let size = decimal(&header[48..58]).ok_or("malformed member size")?;
+        let start = offset + HEADER;
+        let data = archive.get(start..start.saturating_add(size)).ok_or("truncated member")?;
+        offset = ::std::cmp::min(archive.len(), start + size + size % 2);
+        let field = trim_end(&header[..16], b' ');
+        let (name, data) = if field.starts_with(b"#1/") {
+            // BSD names the member with the first bytes of the data
+            let len = decimal(&field[3..]).ok_or("malformed member name")?;
+            let name = data.get(..len).ok_or("truncated member name")?;
+            (trim_end(name, 0), &data[len..])
+        } else if field == b"//" {
+            long_names = data;
+            continue;
+        } else if field == b"/" || field == b"/SYM64/" {
+            continue;
+        } else if field.starts_with(b"/") {
+            let at = decimal(&field[1..]).ok_or("malformed member name")?;
+            let name = long_names.get(at..).ok_or("member name out of bounds")?;
+            let end = name.iter().position(|&b| b == b'\n' || b == 0).unwrap_or(name.len());
+            (trim_end(&name[..end], b'/'), data)
+        } else {
+            (trim_end(field, b'/'), data)
+        };
+        if name.starts_with(b"__.SYMDEF") {
+            continue;
+        }
+        members.push((name.to_vec(), data.to_vec()));
+    }
+    Ok(members)
+}
+
+/// Write an archive of the members, `(name, contents)` pairs, indexing the symbols they define
+///
+/// Like `llvm-ar` in deterministic mode, members have zeroed timestamps, owner and group and
+/// `644` permissions.
+pub fn write(kind: ArchiveKind, members: &[(&[u8], &[u8])]) -> Vec<u8> {
+    let symbols = members.iter().map(|&(_, data)| symbols(data).unwrap_or_default())
+                         .collect::<Vec<_>>();
+    let mut out = MAGIC.to_vec();
+    match kind {
+        ArchiveKind::Bsd => write_bsd(&mut out, members, &symbols),
+        ArchiveKind::Gnu | ArchiveKind::Mips64 | ArchiveKind::Coff =>
+            write_gnu(&mut out, kind, members, &symbols),
+    }
+    out
+}
+
+/// The GNU format, which the COFF format extends with a second, sorted, symbol table
+fn write_gnu(out: &mut Vec<u8>, kind: ArchiveKind, members: &[(&[u8], &[u8])],
+             symbols: &[Vec<Vec<u8>>]) {
+    let word = if kind == ArchiveKind::Mips64 { 8 } else { 4 };
+    let count = symbols.iter().map(|s| s.len()).sum::<usize>();
+    let strings = symbols.iter().flat_map(|s| s.iter()).map(|s| s.len() + 1).sum::<usize>();
+    let first = word + word * count + strings;
+    let second = 4 + 4 * members.len() + 4 + 2 * count + strings;
+    let mut long_names = Vec::new();
+    let names = members.iter().map(|&(name, _)| {
+        if name.len() < 16 && !name.contains(&b'/') {
+            let mut field = name.to_vec();
+            field.push(b'/');
+            field
+        } else {
+            let field = format!("/{}", long_names.len()).into_bytes();
+            long_names.extend_from_slice(name);
+            // COFF terminates the long names with nulls
+            long_names.extend_from_slice(if kind == ArchiveKind::Coff { b"\0" } else { b"/\n" });
+            field
+        }
+    }).collect::<Vec<_>>();
+
+    let mut offset = MAGIC.len() + HEADER + padded(first, 2);
+    if kind == ArchiveKind::Coff {
+        offset += HEADER + padded(second, 2);
+    }
+    if !long_names.is_empty() {
+        offset += HEADER + padded(long_names.len(), 2);
+    }
+    let mut offsets = Vec::new();
+    for &(_, data) in members {
+        offsets.push(offset);
+        offset += HEADER + padded(data.len(), 2);
+    }
+
+    let name: &[u8] = if kind == ArchiveKind::Mips64 { b"/SYM64/" } else { b"/" };
+    header(out, name, first, true);
+    let big_endian = |out: &mut Vec<u8>, value: usize| {
+        let bytes = (value as u64).to_be_bytes();
+        out.extend_from_slice(&bytes[8 - word..]);
+    };
+    big_endian(out, count);
+    for (offset, symbols) in offsets.iter().zip(symbols) {
+        for _ in symbols {
+            big_endian(out, *offset);
+        }
+    }
+    for symbol in symbols.iter().flat_map(|s| s.iter()) {
+        out.extend_from_slice(symbol);
+        out.push(0);
+    }
+    pad(out, 2, b'\n');
+
+    if kind == ArchiveKind::Coff {
+        header(out, b"/", second, true);
+        out.extend_from_slice(&(members.len() as u32).to_le_bytes());
+        for offset in &offsets {
+            out.extend_from_slice(&(*offset as u32).to_le_bytes());
+        }
+        let mut sorted = symbols.iter().enumerate().flat_map(|(idx, s)| {
+            s.iter().map(move |s| (s, idx as u16 + 1))
+        }).collect::<Vec<_>>();
+        sorted.sort();
+        out.extend_from_slice(&(count as u32).to_le_bytes());
+        for &(_, member) in &sorted {
+            out.extend_from_slice(&member.to_le_bytes());
+        }
+        for &(symbol, _) in &sorted {
+            out.extend_from_slice(symbol);
+            out.push(0);
+        }
+        pad(out, 2, b'\n');
+    }
+
+    if !long_names.is_empty() {
+        header(out, b"//", long_names.len(), false);
+        out.extend_from_slice(&long_names);
+        pad(out, 2, b'\n');
+    }
+    for (&(_, data), name) in members.iter().zip(names) {
+        header(out, &name, data.len(), true);
+        out.extend_from_slice(data);
+        pad(out, 2, b'\n');
+    }
+}
+
+/// The BSD format as written for Darwin, with the contents of the members aligned to 8 bytes
+fn write_bsd(out: &mut Vec<u8>, members: &[(&[u8], &[u8])], symbols: &[Vec<Vec<u8>>]) {
+    // The names precede the contents and are padded with nulls, so that the contents following
+    // the 60 byte header and the name are aligned
+    let name_len = |name: &[u8]| padded(name.len() + 4, 8) - 4;
+    let count = symbols.iter().map(|s| s.len()).sum::<usize>();
+    let strings = padded(symbols.iter().flat_map(|s| s.iter()).map(|s| s.len() + 1)
+                                .sum::<usize>(), 8);
+    let symdef: &[u8] = b"__.SYMDEF";
+    let table = 4 + 8 * count + 4 + strings;
+
+    let mut offset = MAGIC.len() + HEADER + name_len(symdef) + table;
+    let mut offsets = Vec::new();
+    for &(name, data) in members {
+        offsets.push(offset);
+        offset += HEADER + name_len(name) + padded(data.len(), 8);
+    }
+
+    bsd_header(out, symdef, name_len(symdef) + table);
+    out.extend_from_slice(&(8 * count as u32).to_le_bytes());
+    let mut string = 0;
+    for (offset, symbols) in offsets.iter().zip(symbols) {
+        for symbol in symbols {
+            out.extend_from_slice(&(string as u32).to_le_bytes());
+            out.extend_from_slice(&(*offset as u32).to_le_bytes());
+            string += symbol.len() + 1;
+        }
+    }
+    out.extend_from_slice(&(strings as u32).to_le_bytes());
+    for symbol in symbols.iter().flat_map(|s| s.iter()) {
+        out.extend_from_slice(symbol);
+        out.push(0);
+    }
+    pad(out, 8, 0);
+
+    for &(name, data) in members {
+        bsd_header(out, name, name_len(name) + padded(data.len(), 8));
+        out.extend_from_slice(data);
+        pad(out, 8, b'\n');
+    }
+}
+
+/// Write the header of a BSD member, followed by its name
+fn bsd_header(out: &mut Vec<u8>, name: &[u8], size: usize) {
+    let len = padded(name.len() + 4, 8) - 4;
+    header(out, format!("#1/{}", len).as_bytes(), size, true);
+    out.extend_from_slice(name);
+    out.resize(out.len() + len - name.len(), 0);
+}
+
+/// Write the header of a member, with zeroed timestamp, owner and group
+///
+/// The table of long names leaves these blank, as well as the permissions.
+fn header(out: &mut Vec<u8>, name: &[u8], size: usize, fields: bool) {
+    let field = |out: &mut Vec<u8>, value: &[u8], width: usize| {
+        out.extend_from_slice(value);
+        out.resize(out.len() + width - value.len(), b' ');
+    };
+    field(out, name, 16);
+    for &(value, width) in &[("0", 12), ("0", 6), ("0", 6), ("644", 8)] {
+        field(out, if fields { value.as_bytes() } else { b"" }, width);
+    }
+    field(out, size.to_string().as_bytes(), 10);
+    out.extend_from_slice(b"`\n");
+}
+
+fn padded(len: usize, align: usize) -> usize {
+    len + (align - len % align) % align
+}
+
+/// Pad the archive written so far to the alignment
+fn pad(out: &mut Vec<u8>, align: usize, byte: u8) {
+    let len = padded(out.len(), align);
+    out.resize(len, byte);
+}
+
+fn decimal(field: &[u8]) -> Option<usize> {
+    ::std::str::from_utf8(field).ok().and_then(|f| f.trim().parse().ok())
+}
+
+fn trim_end(bytes: &[u8], byte: u8) -> &[u8] {
+    let len = bytes.iter().rposition(|&b| b != byte).map_or(0, |p| p + 1);
+    &bytes[..len]
+}
+
+/// The symbols an ELF, Mach-O or COFF object defines and makes visible to other objects
+///
+/// Anything else, such as LLVM bitcode, defines no symbols as far as the archive is concerned.
+fn symbols(object: &[u8]) -> Result<Vec<Vec<u8>>, &'static str> {
+    let magic = Bytes { data: object, le: true }.u32(0)?;
+    if object.starts_with(b"\x7fELF") {
+        elf_symbols(object)
+    } else if [0xfeedface, 0xfeedfacf, 0xcefaedfe, 0xcffaedfe].contains(&magic) {
+        macho_symbols(object)
+    } else if [0x14c, 0x8664, 0xaa64, 0xa641, 0x1c0, 0x1c2, 0x1c4].contains(&(magic & 0xffff)) {
+        coff_symbols(object)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+fn elf_symbols(object: &[u8]) -> Result<Vec<Vec<u8>>, &'static str> {
+    let is64 = object.get(4) == Some(&2);
+    let bytes = Bytes { data: object, le: object.get(5) == Some(&1) };
+    let (shoff, shentsize, mut shnum) = if is64 {
+        (bytes.u64(0x28)?, bytes.u16(0x3a)?, bytes.u16(0x3c)? as u64)
+    } else {
+        (bytes.u32(0x20)? as u64, bytes.u16(0x2e)?, bytes.u16(0x30)? as u64)
+    };
+    // Type, offset, size and link of a section
+    let section = |idx: u64| -> Result<(u32, usize, usize, u32), &'static str> {
+        let at = shoff.saturating_add(idx * shentsize as u64);
+        if at >= object.len() as u64 {
+            return Err("truncated object");
+        }
+        let at = at as usize;
+        Ok(if is64 {
+            (bytes.u32(at + 4)?, bytes.u64(at + 0x18)? as usize,
+             bytes.u64(at + 0x20)? as usize, bytes.u32(at + 0x28)?)
+        } else {
+            (bytes.u32(at + 4)?, bytes.u32(at + 0x10)? as usize,
+             bytes.u32(at + 0x14)? as usize, bytes.u32(at + 0x18)?)
+        })
+    };
+    // Objects with many sections store the number in the size of the first section
+    if shnum == 0 {
+        shnum = section(0)?.2 as u64;
+    }
+    let mut ret = Vec::new();
+    for idx in 0..shnum {
+        let (kind, offset, size, link) = section(idx)?;
+        // SHT_SYMTAB
+        if kind != 2 {
+            continue;
+        }
+        let strings = section(link as u64)?.1;
+        let entry = if is64 { 24 } else { 16 };
+        object.get(offset..offset.saturating_add(size)).ok_or("truncated object")?;
+        // The first symbol is always the null symbol
+        for at in (offset..offset + size).step_by(entry).skip(1) {
+            let (info, shndx) = if is64 {
+                (bytes.u8(at + 4)?, bytes.u16(at + 6)?)
+            } else {
+                (bytes.u8(at + 12)?, bytes.u16(at + 14)?)
+            };
+            // STB_GLOBAL, STB_WEAK or STB_GNU_UNIQUE, and not SHN_UNDEF
+            if [1, 2, 10].contains(&(info >> 4)) && shndx != 0 {
+                let name = bytes.u32(at)? as usize;
+                ret.push(bytes.cstr(strings.saturating_add(name))?.to_vec());
+            }
+        }
+    }
+    Ok(ret)
+}
+
+fn macho_symbols(object: &[u8]) -> Result<Vec<Vec<u8>>, &'static str> {
+    let magic = Bytes { data: object, le: true }.u32(0)?;
+    let bytes = Bytes { data: object, le: magic == 0xfeedface || magic == 0xfeedfacf };
+    let is64 = magic == 0xfeedfacf || magic == 0xcffaedfe;
+    let mut command = if is64 { 32 } else { 28 };
+    let mut ret = Vec::new();
+    for _ in 0..bytes.u32(16)? {
+        // LC_SYMTAB
+        if bytes.u32(command)? == 2 {
+            let symbols = bytes.u32(command + 8)? as usize;
+            let count = bytes.u32(command + 12)? as usize;
+            let strings = bytes.u32(command + 16)? as usize;
+            let entry = if is64 { 16 } else { 12 };
+            for at in (0..count).map(|idx| symbols + idx * entry) {
+                let kind = bytes.u8(at + 4)?;
+                let value = if is64 { bytes.u64(at + 8)? }
+                            else { bytes.u32(at + 8)? as u64 };
+                // Not a debugging symbol, external, and defined or common
+                if kind & 0xe0 == 0 && kind & 0x01 != 0 && (kind & 0x0e != 0 || value != 0) {
+                    let name = bytes.u32(at)? as usize;
+                    ret.push(bytes.cstr(strings + name)?.to_vec());
+                }
+            }
+        }
+        command = command.saturating_add(bytes.u32(command + 4)? as usize);
+    }
+    Ok(ret)
+}
+
+fn coff_symbols(object: &[u8]) -> Result<Vec<Vec<u8>>, &'static str> {
+    let bytes = Bytes { data: object, le: true };
+    let symbols = bytes.u32(8)? as usize;
+    let count = bytes.u32(12)? as usize;
+    let strings = symbols + count * 18;
+    let mut ret = Vec::new();
+    let mut idx = 0;
+    while idx < count {
+        let at = symbols + idx * 18;
+        let section = bytes.u16(at + 12)? as i16;
+        let value = bytes.u32(at + 8)?;
+        // IMAGE_SYM_CLASS_EXTERNAL, and defined or common
+        if bytes.u8(at + 16)? == 2 && (section > 0 || (section == 0 && value != 0)) {
+            let name = if bytes.u32(at)? == 0 {
+                bytes.cstr(strings + bytes.u32(at + 4)? as usize)?
+            } else {
+                trim_end(object.get(at..at + 8).ok_or("truncated object")?, 0)
+            };
+            ret.push(name.to_vec());
+        }
+        idx += 1 + bytes.u8(at + 17)? as usize;
+    }
+    Ok(ret)
+}
+
+/// Bounds checked reads of integers of either endianness
+struct Bytes<'a> {
+    data: &'a [u8],
+    le: bool,
+}
+
+impl<'a> Bytes<'a> {
+    fn get(&self, at: usize, len: usize) -> Result<u64, &'static str> {
+        let bytes = self.data.get(at..at.saturating_add(len)).ok_or("truncated object")?;
+        let fold = |value: u64, &byte: &u8| value << 8 | byte as u64;
+        Ok(if self.le { bytes.iter().rev().fold(0, fold) } else { bytes.iter().fold(0, fold) })
+    }
+
+    fn u8(&self, at: usize) -> Result<u8, &'static str> {
+        self.get(at, 1).map(|v| v as u8)
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, &'static str> {
+        self.get(at, 2).map(|v| v as u16)
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, &'static str> {
+        self.get(at, 4).map(|v| v as u32)
+    }
+
+    fn u64(&self, at: usize) -> Result<u64, &'static str> {
+        self.get(at, 8)
+    }
+
+    /// The null terminated string at the offset
+    fn cstr(&self, at: usize) -> Result<&'a [u8], &'static str> {
+        let rest = self.data.get(at..).ok_or("truncated object")?;
+        let len = rest.iter().position(|&b| b == 0).ok_or("unterminated string")?;
+        Ok(&rest[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ArchiveKind;
+    use super::{decimal, header, padded, read, trim_end, write, HEADER, MAGIC};
+
+    /// A COFF object defining the symbol, of up to 8 bytes
+    fn object(symbol: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        // Machine, sections, timestamp, symbol table, symbols, optional header, characteristics
+        out.extend_from_slice(&0x8664u16.to_le_bytes());
+        out.extend_from_slice(&[0; 6]);
+        out.extend_from_slice(&20u32.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        // Name, value, section, type, storage class and auxiliary symbols
+        out.extend_from_slice(symbol);
+        out.resize(20 + 8, 0);
+        out.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 0, 2, 0]);
+        // The empty string table
+        out.extend_from_slice(&4u32.to_le_bytes());
+        out
+    }
+
+    /// An object, a member of odd size and the longest name GNU stores in the header, followed
+    /// by one too long for it
+    fn members(object: &[u8]) -> Vec<(&[u8], &[u8])> {
+        vec![(b"short.o", object), (b"fifteen_chars.o", b"odd"), (b"sixteen_chars1.o", b"even")]
+    }
+
+    /// The name fields of the archive members, and the offsets of their contents
+    fn headers(archive: &[u8]) -> Vec<(&[u8], usize)> {
+        let mut ret = Vec::new();
+        let mut offset = MAGIC.len();
+        while offset < archive.len() {
+            let size = decimal(&archive[offset + 48..offset + 58]).unwrap();
+            ret.push((trim_end(&archive[offset..offset + 16], b' '), offset + HEADER));
+            offset += HEADER + size + size % 2;
+        }
+        ret
+    }
+
+    fn u32_be(bytes: &[u8]) -> usize {
+        bytes[..4].iter().fold(0, |value, &byte| value << 8 | byte as usize)
+    }
+
+    fn u32_le(bytes: &[u8]) -> usize {
+        bytes[..4].iter().rev().fold(0, |value, &byte| value << 8 | byte as usize)
+    }
+
+    fn owned(members: &[(&[u8], &[u8])]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        members.iter().map(|&(name, data)| (name.to_vec(), data.to_vec())).collect()
+    }
+
+    #[test]
+    fn gnu() {
+        let object = object(b"sym");
+        let archive = write(ArchiveKind::Gnu, &members(&object));
+        let headers = headers(&archive);
+        let names = headers.iter().map(|h| h.0).collect::<Vec<_>>();
+        assert_eq!(names, [&b"/"[..], b"//", b"short.o/", b"fifteen_chars.o/", b"/0"]);
+        let symbols = &archive[headers[0].1..];
+        assert_eq!(u32_be(symbols), 1);
+        assert_eq!(u32_be(&symbols[4..]), headers[2].1 - HEADER);
+        assert!(symbols[8..].starts_with(b"sym\0"));
+        assert!(archive[headers[1].1..].starts_with(b"sixteen_chars1.o/\n"));
+        // The member of odd size is padded
+        assert_eq!(archive[headers[3].1 + 3], b'\n');
+        assert_eq!(headers[4].1 % 2, 0);
+        assert_eq!(read(&archive).unwrap(), owned(&members(&object)));
+    }
+
+    #[test]
+    fn mips64() {
+        let object = object(b"sym");
+        let archive = write(ArchiveKind::Mips64, &members(&object));
+        let headers = headers(&archive);
+        assert_eq!(headers[0].0, b"/SYM64/");
+        let symbols = &archive[headers[0].1..];
+        assert_eq!((u32_be(symbols), u32_be(&symbols[4..])), (0, 1));
+        assert_eq!((u32_be(&symbols[8..]), u32_be(&symbols[12..])), (0, headers[2].1 - HEADER));
+        assert!(symbols[16..].starts_with(b"sym\0"));
+        assert_eq!(read(&archive).unwrap(), owned(&members(&object)));
+    }
+
+    #[test]
+    fn coff() {
+        let object = object(b"sym");
+        let archive = write(ArchiveKind::Coff, &members(&object));
+        let headers = headers(&archive);
+        let names = headers.iter().map(|h| h.0).collect::<Vec<_>>();
+        assert_eq!(names, [&b"/"[..], b"/", b"//", b"short.o/", b"fifteen_chars.o/", b"/0"]);
+        // The second linker member lists the members, then the sorted symbols by member index
+        let second = &archive[headers[1].1..];
+        assert_eq!(u32_le(second), 3);
+        for (idx, member) in headers[3..].iter().enumerate() {
+            assert_eq!(u32_le(&second[4 + 4 * idx..]), member.1 - HEADER);
+        }
+        assert_eq!(u32_le(&second[16..]), 1);
+        assert_eq!(&second[20..22], &1u16.to_le_bytes());
+        assert!(second[22..].starts_with(b"sym\0"));
+        assert!(archive[headers[2].1..].starts_with(b"sixteen_chars1.o\0"));
+        assert_eq!(read(&archive).unwrap(), owned(&members(&object)));
+    }
+
+    #[test]
+    fn bsd() {
+        let object = object(b"sym");
+        let archive = write(ArchiveKind::Bsd, &members(&object));
+        let headers = headers(&archive);
+        let names = headers.iter().map(|h| h.0).collect::<Vec<_>>();
+        assert_eq!(names, [&b"#1/12"[..], b"#1/12", b"#1/20", b"#1/20"]);
+        let symdef = &archive[headers[0].1..];
+        assert!(symdef.starts_with(b"__.SYMDEF\0\0\0"));
+        // The ranlib entries, each the offset of the name and of the member
+        assert_eq!(u32_le(&symdef[12..]), 8);
+        assert_eq!((u32_le(&symdef[16..]), u32_le(&symdef[20..])), (0, headers[1].1 - HEADER));
+        assert_eq!(u32_le(&symdef[24..]), 8);
+        assert!(symdef[28..].starts_with(b"sym\0"));
+        // The contents follow the names, aligned to 8 bytes and padded to the alignment
+        for (header, name) in headers[1..].iter().zip(&[12, 20, 20]) {
+            assert_eq!((header.1 + name) % 8, 0);
+        }
+        let aligned = |data: &[u8]| {
+            let mut data = data.to_vec();
+            data.resize(padded(data.len(), 8), b'\n');
+            data
+        };
+        let expected = members(&object).iter().map(|&(name, data)| (name.to_vec(), aligned(data)))
+                                               .collect::<Vec<_>>();
+        assert_eq!(read(&archive).unwrap(), expected);
+    }
+
+    #[test]
+    fn malformed() {
+        let member = |name: &[u8], size, data: &[u8]| {
+            let mut out = MAGIC.to_vec();
+            header(&mut out, name, size, true);
+            out.extend_from_slice(data);
+            out
+        };
+        let mut terminator = member(b"a.o/", 2, b"ab");
+        terminator[MAGIC.len() + 58] = b'\n';
+        let mut size = member(b"a.o/", 2, b"ab");
+        size[MAGIC.len() + 48] = b'x';
+        let malformed = [
+            (&b""[..], "not an archive"),
+            (b"!<thin>\n", "thin archives are not supported"),
+            (b"!<arch>\na.o/", "truncated member header"),
+            (&terminator, "malformed member header"),
+            (&size, "malformed member size"),
+            (&member(b"a.o/", 100, b"ab"), "truncated member"),
+            (&member(b"a.o/", 9999999999, b"ab"), "truncated member"),
+            (&member(b"#1/x", 2, b"ab"), "malformed member name"),
+            (&member(b"#1/8", 2, b"ab"), "truncated member name"),
+            (&member(b"/x", 2, b"ab"), "malformed member name"),
+            (&member(b"/99", 2, b"ab"), "member name out of bounds"),
+        ];
+        for &(archive, error) in &malformed {
+            assert_eq!(read(archive), Err(error));
+        }
+
+        let object = object(b"sym");
+        for &kind in &[ArchiveKind::Gnu, ArchiveKind::Mips64, ArchiveKind::Bsd, ArchiveKind::Coff] {
+            let archive = write(kind, &members(&object));
+            for len in 0..archive.len() {
+                assert!(read(&archive[..len]).map_or(true, |m| m.len() < 3), "{:?} {}", kind, len);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_objects() {
+        let object = object(b"sym");
+        let mut elf = b"\x7fELF\x02\x01".to_vec();
+        elf.resize(0x40, 0);
+        elf[0x28..0x30].copy_from_slice(&(!0u64 - 2).to_le_bytes());
+        elf[0x3a..0x3e].copy_from_slice(&[0x40, 0, 1, 0]);
+        let mut objects = (0..object.len()).map(|len| &object[..len]).collect::<Vec<_>>();
+        objects.push(&elf);
+        for object in objects {
+            let archive = write(ArchiveKind::Gnu, &[(b"bad.o", object)]);
+            assert_eq!(read(&archive).unwrap(), [(b"bad.o".to_vec(), object.to_vec())]);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Reading existing archives and writing new ones
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use {ArchiveKind, BuildError};
+#[cfg(feature = "system-llvm")]
+use ar;
+#[cfg(not(feature = "system-llvm"))]
+use path_to_cstring;
+#[cfg(not(feature = "system-llvm"))]
+use ffi::*;
+#[cfg(not(feature = "system-llvm"))]
+use libc;
+
+/// A member of an existing archive
+#[cfg(not(feature = "system-llvm"))]
+pub type Child = ArchiveChild;
+
+/// The contents of a member of an existing archive
+#[cfg(feature = "system-llvm")]
+pub type Child = Vec<u8>;
+
+/// An existing archive opened for reading
+pub struct ExistingArchive {
+    /// Path to the archive
+    pub path: PathBuf,
+    /// The members by name, dropped before the archive they belong to
+    pub members: Vec<(CString, Child)>,
+    #[cfg(not(feature = "system-llvm"))]
+    _archive: Archive,
+}
+
+/// Where the contents of a member of the archive being written come from
+pub enum Member<'a> {
+    /// A member of an existing archive
+    Existing(&'a Child),
+    /// The object file at the path
+    Object(&'a CStr),
+}
+
+impl fmt::Debug for ExistingArchive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.members.iter().map(|m| &m.0).collect::<Vec<_>>();
+        f.debug_struct("ExistingArchive").field("path", &self.path).field("members", &names)
+                                          .finish()
+    }
+}
+
+impl ExistingArchive {
+    /// Open the archive and read the list of its members
+    #[cfg(not(feature = "system-llvm"))]
+    pub fn open(path: &Path) -> Result<ExistingArchive, BuildError> {
+        let read_error = |message: String| BuildError::ReadArchive {
+            archive: path.to_path_buf(),
+            message
+        };
+        let cpath = path_to_cstring(path)?;
+        unsafe {
+            // Discard a stale error, so that the end of the members is not mistaken for an error
+            last_error();
+            let raw = LLVMRustOpenArchive(cpath.as_ptr());
+            if raw.is_null() {
+                return Err(read_error(last_error().unwrap_or_else(||
+                    String::from("could not open archive"))));
+            }
+            let archive = Archive(raw);
+            let mut members = Vec::new();
+            let iter = LLVMRustArchiveIteratorNew(archive.0);
+            loop {
+                let child = LLVMRustArchiveIteratorNext(iter);
+                if child.is_null() {
+                    break;
+                }
+                let child = ArchiveChild(child);
+                let mut len = 0;
+                let name = LLVMRustArchiveChildName(child.0, &mut len);
+                let name = if name.is_null() {
+                    Vec::new()
+                } else {
+                    ::std::slice::from_raw_parts(name as *const u8, len).to_vec()
+                };
+                match CString::new(name) {
+                    Ok(name) => members.push((name, child)),
+                    Err(_) => {
+                        LLVMRustArchiveIteratorFree(iter);
+                        return Err(read_error(String::from("member name contains nulls")));
+                    }
+                }
+            }
+            LLVMRustArchiveIteratorFree(iter);
+            if let Some(message) = last_error() {
+                return Err(read_error(message));
+            }
+            Ok(ExistingArchive {
+                path: path.to_path_buf(),
+                members,
+                _archive: archive
+            })
+        }
+    }
+
+    /// Open the archive and read its members
+    #[cfg(feature = "system-llvm")]
+    pub fn open(path: &Path) -> Result<ExistingArchive, BuildError> {
+        let read_error = |message: String| BuildError::ReadArchive {
+            archive: path.to_path_buf(),
+            message: message
+        };
+        let contents = ::std::fs::read(path).map_err(|e| read_error(e.to_string()))?;
+        let mut members = Vec::new();
+        for (name, data) in ar::read(&contents).map_err(|e| read_error(String::from(e)))? {
+            let name = CString::new(name).map_err(|_|
+                       read_error(String::from("member name contains nulls")))?;
+            members.push((name, data));
+        }
+        Ok(ExistingArchive { path: path.to_path_buf(), members: members })
+    }
+}
+
+/// Write the archive of the members, `(name, member)` pairs, with an index of their symbols
+#[cfg(not(feature = "system-llvm"))]
+pub fn write(archive: &Path, kind: ArchiveKind, members: &[(&CStr, Member)])
+-> Result<(), BuildError> {
+    let invalid_path = |reason| BuildError::InvalidPath {
+        path: archive.to_path_buf(),
+        reason
+    };
+    let libname_str = archive.to_str().ok_or_else(||
+                      invalid_path("archive filename is not utf-8"))?;
+    let dest = CString::new(libname_str).map_err(|_|
+               invalid_path("output file has interior nulls"))?;
+    unsafe {
+        let members = members.iter().map(|&(name, ref member)| {
+            let (object, child) = match *member {
+                Member::Existing(child) => (::std::ptr::null(), child.0),
+                Member::Object(path) => (path.as_ptr(), ::std::ptr::null_mut()),
+            };
+            LLVMRustArchiveMemberNew(object, name.as_ptr(), child)
+        }).collect::<Vec<_>>();
+        let kind = match kind {
+            // rustc has no kind for MIPS64, LLVM switches GNU archives to a 64-bit symbol table
+            // once the offsets need it
+            ArchiveKind::Gnu | ArchiveKind::Mips64 => LLVMRustArchiveKind::Gnu,
+            // The flavour of BSD archives Apple's linker expects
+            ArchiveKind::Bsd => LLVMRustArchiveKind::Darwin,
+            ArchiveKind::Coff => LLVMRustArchiveKind::Coff,
+        };
+        let r = LLVMRustWriteArchive(dest.as_ptr(),
+                                     members.len() as libc::size_t,
+                                     members.as_ptr(),
+                                     true,
+                                     kind,
+                                     false);
+        for member in members {
+            LLVMRustArchiveMemberFree(member);
+        }
+        if r != 0 {
+            return Err(BuildError::WriteArchive {
+                archive: archive.to_path_buf(),
+                message: last_error().unwrap_or_else(|| "failed to write archive".to_string())
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Write the archive of the members, `(name, member)` pairs, with an index of their symbols
+#[cfg(feature = "system-llvm")]
+pub fn write(archive: &Path, kind: ArchiveKind, members: &[(&CStr, Member)])
+-> Result<(), BuildError> {
+    let write_error = |e: ::std::io::Error| BuildError::WriteArchive {
+        archive: archive.to_path_buf(),
+        message: e.to_string()
+    };
+    let mut objects = Vec::new();
+    for &(_, ref member) in members {
+        if let Member::Object(path) = *member {
+            let path = Path::new(path.to_str().expect("checked by path_to_cstring"));
+            objects.push(::std::fs::read(path).map_err(&write_error)?);
+        }
+    }
+    let mut objects = objects.iter();
+    let members = members.iter().map(|&(name, ref member)| {
+        let data = match *member {
+            Member::Existing(child) => &child[..],
+            Member::Object(_) => &objects.next().expect("read above")[..],
+        };
+        (name.to_bytes(), data)
+    }).collect::<Vec<_>>();
+    ::std::fs::write(archive, ar::write(kind, &members)).map_err(&write_error)
+}
+
+/// List the names of the members of an existing archive
+///
+/// Corresponds to `ar t`.
+pub fn archive_members<P: AsRef<Path>>(archive: P) -> Result<Vec<String>, BuildError> {
+    let existing = ExistingArchive::open(archive.as_ref())?;
+    Ok(existing.members.iter().map(|(name, _)| name.to_string_lossy().into_owned()).collect())
+}
@@ -0,0 +1,172 @@
+//! Code generators building the objects of the inputs and the archives of the objects
+use std::ffi::CStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use {compile_module, initialize_llvm, link_modules, parse_input, path_to_cstring, verify_input,
+     ArchiveKind, BuildError, BuildOptions, Input};
+use archive::{self, ExistingArchive, Member};
+use ffi::*;
+
+/// Version of the rustc this crate is linked to, which also determines the version of LLVM, or
+/// the version of the system LLVM
+pub const RUSTC_VERSION: &str = env!("LLVM_BUILD_UTILS_RUSTC_VERSION");
+
+/// A code generator, building the objects of the inputs and the archives of the objects
+///
+/// `InProcess` is the default. `ExternalTools` drives the executables of an LLVM installation
+/// instead. Any other backend may be used with `Build::backend`, e.g. a fake one in tests.
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Version of the code generator
+    ///
+    /// Objects cached by previous builds are only reused by a backend of the same version, so
+    /// a version which cannot be determined is an error rather than a guess.
+    fn version(&self) -> Result<String, BuildError>;
+
+    /// Parse the inputs of an archive member
+    ///
+    /// The inputs are linked into a single object in this order. The first input is the one
+    /// reported in errors which do not relate to a specific input. In `deterministic` mode the
+    /// object must not depend on the directory the inputs are in.
+    fn parse(&self, inputs: &[Input], opt: &BuildOptions, deterministic: bool)
+    -> Result<Box<dyn Parsed>, BuildError>;
+
+    /// Write the archive of the members, `(name, member)` pairs, with an index of their symbols
+    ///
+    /// The archive replaces the file at `archive`, if there is one.
+    fn write_archive(&self, archive: &Path, kind: ArchiveKind, members: &[(&CStr, ArchiveMember)])
+    -> Result<(), BuildError>;
+}
+
+/// The inputs of an archive member, parsed by a `Backend`
+pub trait Parsed {
+    /// Check that the inputs are valid
+    fn verify(&self) -> Result<(), BuildError>;
+
+    /// Link the inputs and generate their machine code into the object file at `object`
+    ///
+    /// The assembly listing `BuildOptions::asm_listing` asks for is written to `listing`. Rust
+    /// declarations of the functions defined by the object are generated if `externs` is set.
+    fn emit_object(self: Box<Self>, object: &Path, listing: Option<&Path>, externs: bool)
+    -> Result<Emitted, BuildError>;
+}
+
+/// What generating an object reports besides the object itself
+#[derive(Clone, Debug, Default)]
+pub struct Emitted {
+    /// Rust declarations of the functions defined by the object, if requested
+    pub externs: Option<String>,
+    /// Definitions renamed in the object, as `(original, renamed)` pairs
+    pub renamed: Vec<(String, String)>,
+    /// Symbols listed in `BuildOptions::exports` the object defines
+    pub exported: Vec<String>,
+    /// Problems which did not prevent generating the object, printed as cargo warnings
+    pub warnings: Vec<String>,
+}
+
+/// Where the contents of a member of the archive being written come from
+#[derive(Clone, Copy, Debug)]
+pub enum ArchiveMember<'a> {
+    /// The object file at the path
+    Object(&'a Path),
+    /// The member of the existing archive, by its index in `ExistingArchive::members`
+    Existing(&'a ExistingArchive, usize),
+}
+
+/// The LLVM this crate is linked to
+///
+/// That is the LLVM of rustc or, with the `system-llvm` feature, the LLVM of `llvm-config`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InProcess;
+
+/// Inputs parsed by `InProcess`, in a context of their own
+struct Modules {
+    /// The modules with the source of the textual IR inputs, dropped before the context
+    modules: Vec<(Module, Option<String>)>,
+    /// Names of the inputs the modules were parsed from
+    inputs: Vec<PathBuf>,
+    opt: BuildOptions,
+    deterministic: bool,
+    ctx: Context,
+}
+
+impl Backend for InProcess {
+    fn version(&self) -> Result<String, BuildError> {
+        Ok(String::from(RUSTC_VERSION))
+    }
+
+    fn parse(&self, inputs: &[Input], opt: &BuildOptions, deterministic: bool)
+    -> Result<Box<dyn Parsed>, BuildError> {
+        initialize_llvm();
+        unsafe {
+            let ctx = LLVMContextCreate();
+            if ctx.is_null() {
+                return Err(BuildError::Context);
+            }
+            let mut parsed = Modules {
+                modules: Vec::new(),
+                inputs: inputs.iter().map(|i| i.name().to_path_buf()).collect(),
+                opt: opt.clone(),
+                deterministic,
+                ctx: Context(ctx),
+            };
+            for input in inputs {
+                let module = parse_input(parsed.ctx.0, input)?;
+                parsed.modules.push(module);
+            }
+            Ok(Box::new(parsed))
+        }
+    }
+
+    fn write_archive(&self, archive: &Path, kind: ArchiveKind, members: &[(&CStr, ArchiveMember)])
+    -> Result<(), BuildError> {
+        let mut objects = Vec::new();
+        for &(_, member) in members {
+            if let ArchiveMember::Object(path) = member {
+                objects.push(path_to_cstring(path)?);
+            }
+        }
+        let mut objects = objects.iter();
+        let mut written = Vec::new();
+        for &(name, member) in members {
+            let member = match member {
+                ArchiveMember::Object(_) =>
+                    Member::Object(objects.next().expect("converted above")),
+                ArchiveMember::Existing(existing, idx) => {
+                    match existing.members.get(idx) {
+                        Some(child) => Member::Existing(&child.1),
+                        None => return Err(BuildError::ReadArchive {
+                            archive: existing.path.clone(),
+                            message: format!("the archive has no member at index {}", idx)
+                        }),
+                    }
+                }
+            };
+            written.push((name, member));
+        }
+        archive::write(archive, kind, &written)
+    }
+}
+
+impl Parsed for Modules {
+    fn verify(&self) -> Result<(), BuildError> {
+        for (parsed, input) in self.modules.iter().zip(&self.inputs) {
+            let source = parsed.1.as_ref().map(|s| &s[..]);
+            unsafe { verify_input(&parsed.0, input, source)? };
+        }
+        Ok(())
+    }
+
+    fn emit_object(self: Box<Self>, object: &Path, listing: Option<&Path>, externs: bool)
+    -> Result<Emitted, BuildError> {
+        let Modules { modules, inputs, opt, deterministic, ctx } = *self;
+        unsafe {
+            let mut warnings = Vec::new();
+            let module = link_modules(ctx.0, modules, &inputs, &opt, deterministic,
+                                      &mut warnings)?;
+            let emitted = compile_module(module, &inputs[0], &opt, deterministic, object, listing,
+                                         externs)?;
+            Ok(Emitted { warnings, ..emitted })
+        }
+    }
+}
@@ -0,0 +1,473 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use {write_archive, ArchiveKind, AssemblySyntax, Backend, BuildError, BuildOptions, CodegenModel,
+     DebugInfo, Input, Optimisation, Passes, Printout, Relocations, Settings, Triple};
+use matrix::{TargetArchive, TargetArchives};
+use multiversion::Multiversion;
+
+/// A builder for an archive
+///
+/// Modelled after `cc::Build`: options set on the builder apply to every file added with
+/// [`file`](#method.file), while files added with [`file_with`](#method.file_with) are built with
+/// their own `BuildOptions`.
+///
+/// ```rust,no_run
+/// extern crate llvm_build_utils;
+/// use llvm_build_utils::*;
+///
+/// fn main() {
+///     Build::new()
+///         .file("a.ll")
+///         .file_with("b.ll", BuildOptions {
+///             attr: String::from("+avx2"),
+///             ..BuildOptions::default()
+///         })
+///         .cpu("x86-64")
+///         .compile("foo");
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Build {
+    defaults: BuildOptions,
+    inputs: Vec<(Input, Option<BuildOptions>)>,
+    multiversioned: Vec<Multiversion>,
+    out_dir: Option<PathBuf>,
+    kind: Option<ArchiveKind>,
+    cargo_metadata: bool,
+    settings: Settings,
+}
+
+impl Build {
+    /// Construct a new builder with `BuildOptions::default()` as the shared options
+    pub fn new() -> Build {
+        Build {
+            defaults: BuildOptions::default(),
+            inputs: Vec::new(),
+            multiversioned: Vec::new(),
+            out_dir: None,
+            kind: None,
+            cargo_metadata: true,
+            settings: Settings::default(),
+        }
+    }
+
+    /// Add a file to be built with the options shared by this builder
+    pub fn file<P: AsRef<Path>>(&mut self, p: P) -> &mut Build {
+        self.input(p.as_ref())
+    }
+
+    /// Add files to be built with the options shared by this builder
+    pub fn files<P, I>(&mut self, files: I) -> &mut Build
+    where P: AsRef<Path>, I: IntoIterator<Item=P> {
+        for file in files {
+            self.file(file);
+        }
+        self
+    }
+
+    /// Add a file to be built with its own options
+    ///
+    /// The options set on this builder do not apply to the file.
+    pub fn file_with<P: AsRef<Path>>(&mut self, p: P, options: BuildOptions) -> &mut Build {
+        self.input_with(p.as_ref(), options)
+    }
+
+    /// Add an input to be built with the options shared by this builder
+    ///
+    /// ```rust,no_run
+    /// extern crate llvm_build_utils;
+    /// use llvm_build_utils::*;
+    ///
+    /// fn main() {
+    ///     let ir = format!("@answer = constant i32 {}", 6 * 7);
+    ///     Build::new().input(Input::memory("answer.ll", ir)).compile("answer");
+    /// }
+    /// ```
+    pub fn input<I: Into<Input>>(&mut self, input: I) -> &mut Build {
+        self.inputs.push((input.into(), None));
+        self
+    }
+
+    /// Add an input to be built with its own options
+    ///
+    /// The options set on this builder do not apply to the input.
+    pub fn input_with<I: Into<Input>>(&mut self, input: I, options: BuildOptions) -> &mut Build {
+        self.inputs.push((input.into(), Some(options)));
+        self
+    }
+
+    /// Add an input to be built once for every set of target features, with a dispatcher choosing
+    /// between the builds at runtime
+    ///
+    /// The sets of features are in the format of `BuildOptions::attr` and go from the least to
+    /// the most preferred. Every set is built with the options shared by this builder, the
+    /// externally visible functions suffixed with the features, e.g. `sum_avx2`. The dispatchers
+    /// are Rust functions exported under the original names, which call the most preferred
+    /// build the CPU supports, as detected on the first call. They are written to
+    /// `<out_dir>/<name>_dispatch.rs`, to be included by the crate:
+    ///
+    /// ```rust,ignore
+    /// include!(concat!(env!("OUT_DIR"), "/sum_dispatch.rs"));
+    /// ```
+    ///
+    /// Runtime detection of features is available on x86 and AArch64. One of the sets must not
+    /// enable any features, to be the fallback for CPUs supporting none of the sets after it.
+    ///
+    /// ```rust,no_run
+    /// extern crate llvm_build_utils;
+    /// use llvm_build_utils::*;
+    ///
+    /// fn main() {
+    ///     Build::new().multiversion("sum.ll", ["", "+avx2", "+avx512f"]).compile("sum");
+    /// }
+    /// ```
+    pub fn multiversion<I, V, S>(&mut self, input: I, variants: V) -> &mut Build
+    where I: Into<Input>, V: IntoIterator<Item=S>, S: Into<String> {
+        self.multiversioned.push(Multiversion {
+            input: input.into(),
+            variants: variants.into_iter().map(|v| v.into()).collect(),
+        });
+        self
+    }
+
+    /// Set all of the shared options at once
+    pub fn options(&mut self, options: BuildOptions) -> &mut Build {
+        self.defaults = options;
+        self
+    }
+
+    /// Set the target triple (see `BuildOptions::triple`)
+    pub fn triple<S: Into<String>>(&mut self, triple: S) -> &mut Build {
+        self.defaults.triple = triple.into();
+        self
+    }
+
+    /// Set the target CPU (see `BuildOptions::cpu`)
+    pub fn cpu<S: Into<String>>(&mut self, cpu: S) -> &mut Build {
+        self.defaults.cpu = cpu.into();
+        self
+    }
+
+    /// Set the target features (see `BuildOptions::attr`)
+    pub fn attr<S: Into<String>>(&mut self, attr: S) -> &mut Build {
+        self.defaults.attr = attr.into();
+        self
+    }
+
+    /// Set the code model (see `BuildOptions::model`)
+    pub fn model(&mut self, model: CodegenModel) -> &mut Build {
+        self.defaults.model = model;
+        self
+    }
+
+    /// Set the relocation model (see `BuildOptions::reloc`)
+    pub fn reloc(&mut self, reloc: Relocations) -> &mut Build {
+        self.defaults.reloc = reloc;
+        self
+    }
+
+    /// Set the optimisation level (see `BuildOptions::opt`)
+    pub fn opt(&mut self, opt: Optimisation) -> &mut Build {
+        self.defaults.opt = opt;
+        self
+    }
+
+    /// Set the IR optimisation pipeline (see `BuildOptions::passes`)
+    pub fn passes(&mut self, passes: Passes) -> &mut Build {
+        self.defaults.passes = passes;
+        self
+    }
+
+    /// Write assembly listings in the given syntax (see `BuildOptions::asm_listing`)
+    pub fn asm_listing(&mut self, syntax: AssemblySyntax) -> &mut Build {
+        self.defaults.asm_listing = Some(syntax);
+        self
+    }
+
+    /// Set the debug information to generate (see `BuildOptions::debug`)
+    pub fn debug(&mut self, debug: DebugInfo) -> &mut Build {
+        self.defaults.debug = debug;
+        self
+    }
+
+    /// Prefix the names of the externally visible definitions (see `BuildOptions::symbol_prefix`)
+    pub fn symbol_prefix<S: Into<String>>(&mut self, prefix: S) -> &mut Build {
+        self.defaults.symbol_prefix = prefix.into();
+        self
+    }
+
+    /// Rename a symbol (see `BuildOptions::symbol_renames`)
+    pub fn rename_symbol<S, T>(&mut self, symbol: S, renamed: T) -> &mut Build
+    where S: Into<String>, T: Into<String> {
+        self.defaults.symbol_renames.insert(symbol.into(), renamed.into());
+        self
+    }
+
+    /// Keep a symbol externally visible, internalising the symbols not exported (see
+    /// `BuildOptions::exports`)
+    pub fn export<S: Into<String>>(&mut self, symbol: S) -> &mut Build {
+        self.defaults.exports.get_or_insert_with(Default::default).insert(symbol.into());
+        self
+    }
+
+    /// Set the directory the archive is written to
+    ///
+    /// *Defaults* to the `$OUT_DIR` environment variable.
+    pub fn out_dir<P: AsRef<Path>>(&mut self, out_dir: P) -> &mut Build {
+        self.out_dir = Some(out_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the format of the archive
+    ///
+    /// *Defaults* to the format conventionally used by the target of the shared options.
+    pub fn archive_kind(&mut self, kind: ArchiveKind) -> &mut Build {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Set the maximum number of inputs compiled concurrently
+    ///
+    /// The concurrency is further bounded by cargo’s jobserver, if there is one.
+    ///
+    /// *Defaults* to the `$NUM_JOBS` environment variable (set by cargo) and 1 if not set.
+    pub fn jobs(&mut self, jobs: usize) -> &mut Build {
+        self.settings.jobs = jobs;
+        self
+    }
+
+    /// Whether to reuse objects compiled by previous builds
+    ///
+    /// Objects are cached in the output directory, keyed by the name and contents of the input,
+    /// the options used to build it and the versions of this crate, rustc and LLVM. Nothing is ever
+    /// evicted from the cache, which keeps growing until the output directory is removed.
+    ///
+    /// *Defaults* to `true`.
+    pub fn cache(&mut self, cache: bool) -> &mut Build {
+        self.settings.cache = cache;
+        self
+    }
+
+    /// Whether to link the inputs sharing the same options into a single object
+    ///
+    /// Linked inputs may refer to each other’s definitions, which then may be inlined across the
+    /// inputs. Definitions of the same symbol in more than one input are reported as an error.
+    ///
+    /// *Defaults* to `false`, every input becoming an object of its own.
+    ///
+    /// Corresponds to `llvm-link`.
+    pub fn link(&mut self, link: bool) -> &mut Build {
+        self.settings.link = link;
+        self
+    }
+
+    /// Whether to write the Rust declarations of the functions defined by the archive
+    ///
+    /// The declarations of the externally visible functions are written to `<out_dir>/<name>.rs`,
+    /// to be included by the crate:
+    ///
+    /// ```rust,ignore
+    /// include!(concat!(env!("OUT_DIR"), "/rdrand.rs"));
+    /// ```
+    ///
+    /// Building fails if a function has no C-ABI-safe Rust equivalent. Integers are declared
+    /// unsigned unless marked `signext`, pointers as `*mut c_void`.
+    ///
+    /// *Defaults* to `false`.
+    pub fn extern_decls(&mut self, extern_decls: bool) -> &mut Build {
+        self.settings.externs = extern_decls;
+        self
+    }
+
+    /// Put the members of an existing archive into the archive
+    ///
+    /// The members of the existing archives come first, in the order the archives were added,
+    /// followed by the built objects. Several archives may be added to merge them into one.
+    pub fn archive<P: AsRef<Path>>(&mut self, archive: P) -> &mut Build {
+        self.settings.archives.push(archive.as_ref().to_path_buf());
+        self
+    }
+
+    /// Whether members replace the earlier members of the same name
+    ///
+    /// A replacing member takes the place of the replaced one. Otherwise the archive may end up
+    /// with several members of the same name.
+    ///
+    /// *Defaults* to `false`.
+    ///
+    /// Corresponds to the difference between `ar r` and `ar q`.
+    pub fn replace_members(&mut self, replace: bool) -> &mut Build {
+        self.settings.replace = replace;
+        self
+    }
+
+    /// Whether the archive must be reproducible bit for bit
+    ///
+    /// In deterministic mode the objects do not depend on the directory the inputs are in.
+    /// Members are always named after their inputs (see `BuildOptions::ar_section_name`),
+    /// archived in a stable order and with zeroed timestamps, owner and group and fixed
+    /// permissions.
+    ///
+    /// *Defaults* to `false`.
+    pub fn deterministic(&mut self, deterministic: bool) -> &mut Build {
+        self.settings.deterministic = deterministic;
+        self
+    }
+
+    /// The code generator to build the archive with
+    ///
+    /// Only the archive is built with the backend. Querying the targets, analysing the inputs
+    /// for `multiversion` and `assembly` use the LLVM this crate is linked to regardless.
+    ///
+    /// *Defaults* to `InProcess`, the LLVM this crate is linked to.
+    ///
+    /// ```rust,no_run
+    /// extern crate llvm_build_utils;
+    /// use llvm_build_utils::*;
+    ///
+    /// fn main() {
+    ///     Build::new().file("input.ll").backend(ExternalTools::from_env()).compile("yourthing");
+    /// }
+    /// ```
+    pub fn backend<B: Backend + 'static>(&mut self, backend: B) -> &mut Build {
+        self.settings.backend = Arc::new(backend);
+        self
+    }
+
+    /// Whether `compile` should print the cargo directives
+    ///
+    /// *Defaults* to `true`.
+    pub fn cargo_metadata(&mut self, cargo_metadata: bool) -> &mut Build {
+        self.cargo_metadata = cargo_metadata;
+        self
+    }
+
+    /// Build the archive, panicking on failure
+    ///
+    /// `name` is the name of the library, e.g. `foo`. The archive is named following the
+    /// conventions of the target: `libfoo.a` on most targets, `foo.lib` on MSVC. Upon success the
+    /// cargo directives to link the library are printed, unless disabled with
+    /// [`cargo_metadata`](#method.cargo_metadata).
+    pub fn compile(&self, name: &str) {
+        match self.try_compile(name) {
+            Ok(printout) => if self.cargo_metadata { printout.print() },
+            Err(e) => panic!("could not build {}: {}", name, e),
+        }
+    }
+
+    /// Build the archive
+    ///
+    /// See [`compile`](#method.compile). Never prints cargo directives.
+    pub fn try_compile(&self, name: &str) -> Result<Printout, BuildError> {
+        let name = library_name(name);
+        let out_dir = self.output_dir();
+        let kind = self.kind.unwrap_or_else(|| ArchiveKind::for_target(&self.defaults.triple));
+        let mut variants = Vec::new();
+        let mut dispatchers = String::new();
+        for multiversioned in &self.multiversioned {
+            let (inputs, dispatcher) = multiversioned.expand(&self.defaults)?;
+            variants.extend(inputs);
+            dispatchers.push_str(&dispatcher);
+        }
+        let inputs = self.inputs.iter().map(|(input, opt)| {
+            (input, opt.as_ref().unwrap_or(&self.defaults))
+        }).chain(variants.iter().map(|(input, opt)| (input, opt))).collect::<Vec<_>>();
+        let mut printout = write_archive(kind, &self.archive_path(name),
+                                         String::from(name), &inputs, &self.settings)?;
+        if !self.multiversioned.is_empty() {
+            let path = out_dir.join(format!("{}_dispatch.rs", name));
+            fs::write(&path, dispatchers).map_err(|e|
+                BuildError::ExternDecls { path: path.clone(), error: e })?;
+            printout.dispatcher = Some(path);
+        }
+        Ok(printout)
+    }
+
+    /// Build an archive of the inputs for each of the Rust targets
+    ///
+    /// Every input is built for each target, overriding the triple of the shared options and of
+    /// the options given to [`file_with`](#method.file_with). The archive of a target is put into
+    /// a directory named after the target within the output directory, created as needed, e.g.
+    /// `$OUT_DIR/x86_64-pc-windows-msvc/foo.lib`. It is of the kind conventionally used by the
+    /// target, unless one has been set with [`archive_kind`](#method.archive_kind).
+    ///
+    /// A target failing to build does not prevent the others from being built. Never prints cargo
+    /// directives.
+    ///
+    /// ```rust,no_run
+    /// extern crate llvm_build_utils;
+    /// use llvm_build_utils::*;
+    ///
+    /// fn main() {
+    ///     let archives = Build::new()
+    ///         .file("a.ll")
+    ///         .compile_targets("foo", &["x86_64-unknown-linux-gnu", "aarch64-apple-darwin"]);
+    ///     print!("{}", archives);
+    ///     assert!(archives.all_succeeded());
+    /// }
+    /// ```
+    pub fn compile_targets<I, S>(&self, name: &str, targets: I) -> TargetArchives
+    where I: IntoIterator<Item=S>, S: AsRef<str> {
+        let out_dir = self.output_dir();
+        targets.into_iter().map(|target| {
+            let target = target.as_ref();
+            let mut build = self.clone();
+            build.out_dir = Some(out_dir.join(target));
+            let result = Triple::from_rust_target(target).and_then(|triple| {
+                let triple = triple.to_string();
+                build.defaults.triple = triple.clone();
+                for &mut (_, ref mut opt) in &mut build.inputs {
+                    if let Some(ref mut opt) = *opt {
+                        opt.triple = triple.clone();
+                    }
+                }
+                let dir = out_dir.join(target);
+                fs::create_dir_all(&dir).map_err(|e|
+                    BuildError::OutputDir { path: dir, error: e })?;
+                build.try_compile(name)
+            });
+            TargetArchive {
+                target: String::from(target),
+                archive: build.archive_path(library_name(name)),
+                result
+            }
+        }).collect()
+    }
+
+    /// The directory the archive is put into
+    fn output_dir(&self) -> PathBuf {
+        self.out_dir.clone().unwrap_or_else(||
+            PathBuf::from(env::var_os("OUT_DIR").unwrap_or_default()))
+    }
+
+    /// Path to the archive of the library, named following the conventions of the target
+    fn archive_path(&self, name: &str) -> PathBuf {
+        self.output_dir().join(if self.defaults.triple.contains("msvc") {
+            format!("{}.lib", name)
+        } else {
+            format!("lib{}.a", name)
+        })
+    }
+}
+
+impl Default for Build {
+    fn default() -> Build {
+        Build::new()
+    }
+}
+
+/// Strip the platform specific decorations off a library name
+///
+/// `libfoo.a`, `foo.lib` and `foo` all become `foo`.
+fn library_name(name: &str) -> &str {
+    if let Some(stem) = name.strip_prefix("lib").and_then(|n| n.strip_suffix(".a")) {
+        stem
+    } else if let Some(stem) = name.strip_suffix(".lib") {
+        stem
+    } else {
+        name
+    }
+}
@@ -0,0 +1,257 @@
+//! Cache of compiled objects, keyed by the content of the input and the options used
+//!
+//! Entries are never evicted, the cache grows with every distinct build until `OUT_DIR` is
+//! removed, e.g. by `cargo clean`.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use {AssemblySyntax, BuildOptions, DebugInfo, Emitted, Input, Passes};
+
+/// FNV-1a, which unlike the hashers of the standard library is the same in every Rust release
+///
+/// The keys of the cache outlive the build script, so must not change with the toolchain. For
+/// the same reason the key is written in as bytes of its own encoding rather than through `Hash`,
+/// the implementations of which may change: numbers are written as little endian `u64` and
+/// everything of variable length is preceded by its length.
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> StableHasher {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.write(bytes);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    fn options(&mut self, opt: &BuildOptions) {
+        // Destructured, so that a new option cannot be left out of the key
+        let BuildOptions { ref triple, ref cpu, ref attr, model, reloc, opt, ref passes,
+                           ref ar_section_name, asm_listing, ref symbol_prefix,
+                           ref symbol_renames, ref exports, debug } = *opt;
+        self.str(triple);
+        self.str(cpu);
+        self.str(attr);
+        self.u64(model as u64);
+        self.u64(reloc as u64);
+        self.u64(opt as u64);
+        match *passes {
+            Passes::None => self.u64(0),
+            Passes::Standard => self.u64(1),
+            Passes::Custom(ref pipeline) => {
+                self.u64(2);
+                self.str(pipeline);
+            }
+        }
+        self.str(ar_section_name);
+        self.u64(match asm_listing {
+            None => 0,
+            Some(AssemblySyntax::Att) => 1,
+            Some(AssemblySyntax::Intel) => 2,
+        });
+        self.str(symbol_prefix);
+        self.u64(symbol_renames.len() as u64);
+        for (name, renamed) in symbol_renames {
+            self.str(name);
+            self.str(renamed);
+        }
+        match *exports {
+            None => self.u64(0),
+            Some(ref exports) => {
+                self.u64(1 + exports.len() as u64);
+                for export in exports {
+                    self.str(export);
+                }
+            }
+        }
+        self.u64(match debug {
+            DebugInfo::Strip => 0,
+            DebugInfo::Preserve => 1,
+            DebugInfo::LineTables => 2,
+        });
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A directory holding previously compiled objects
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    /// Version of the backend the objects are compiled with
+    version: String,
+}
+
+/// Objects (and listings, Rust declarations, symbols and warnings) found in the cache
+pub struct Entry {
+    pub object: PathBuf,
+    pub listing: Option<PathBuf>,
+    pub externs: Option<String>,
+    pub renamed: Vec<(String, String)>,
+    pub exported: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Cache {
+    /// The cache stored in `outdir`, of objects compiled by the backend of the given version
+    pub fn new(outdir: &Path, version: String) -> Cache {
+        Cache { dir: outdir.join("llvm_build_utils-cache"), version }
+    }
+
+    /// Compute the key of inputs with the given contents built with the given options
+    ///
+    /// The key covers the names of the inputs, which end up in the objects, and the version of
+    /// this crate and of the backend as well.
+    pub fn key<C: AsRef<[u8]>>(&self, inputs: &[Input], contents: &[C], opt: &BuildOptions,
+                               deterministic: bool)
+    -> String {
+        // Two differently seeded 64-bit hashes make collisions a non-concern.
+        let mut hashes = [0u64; 2];
+        for (seed, hash) in hashes.iter_mut().enumerate() {
+            let mut hasher = StableHasher::new();
+            hasher.u64(seed as u64);
+            hasher.str(env!("CARGO_PKG_VERSION"));
+            hasher.str(&self.version);
+            hasher.options(opt);
+            hasher.u64(deterministic as u64);
+            hasher.u64(contents.len() as u64);
+            for c in contents {
+                hasher.bytes(c.as_ref());
+            }
+            hasher.u64(inputs.len() as u64);
+            for input in inputs {
+                hasher.str(&input.name().to_string_lossy());
+            }
+            // Line tables refer to the inputs relative to the current directory
+            if opt.debug == DebugInfo::LineTables && !deterministic {
+                let dir = ::std::env::current_dir().unwrap_or_default();
+                hasher.str(&dir.to_string_lossy());
+            }
+            *hash = hasher.finish();
+        }
+        format!("{:016x}{:016x}", hashes[0], hashes[1])
+    }
+
+    /// Look the key up in the cache
+    ///
+    /// Only the files `opt` (and `externs`) call for are required to be present.
+    pub fn lookup(&self, key: &str, opt: &BuildOptions, externs: bool) -> Option<Entry> {
+        let listing = opt.asm_listing.is_some();
+        let object = self.dir.join(format!("{}.o", key));
+        let listing_path = self.dir.join(format!("{}.s", key));
+        if !object.is_file() || (listing && !listing_path.is_file()) {
+            return None;
+        }
+        let externs = if externs {
+            match fs::read_to_string(self.dir.join(format!("{}.rs", key))) {
+                Ok(e) => Some(e),
+                Err(_) => return None,
+            }
+        } else {
+            None
+        };
+        let renamed = if !opt.symbol_prefix.is_empty() || !opt.symbol_renames.is_empty() {
+            match fs::read_to_string(self.dir.join(format!("{}.sym", key))) {
+                Ok(r) => r.lines().filter_map(|l| {
+                    let mut names = l.splitn(2, '\t');
+                    match (names.next(), names.next()) {
+                        (Some(old), Some(new)) => Some((String::from(old), String::from(new))),
+                        _ => None,
+                    }
+                }).collect(),
+                Err(_) => return None,
+            }
+        } else {
+            Vec::new()
+        };
+        let exported = if opt.exports.is_some() {
+            match fs::read_to_string(self.dir.join(format!("{}.exp", key))) {
+                Ok(e) => e.lines().map(String::from).collect(),
+                Err(_) => return None,
+            }
+        } else {
+            Vec::new()
+        };
+        // Line tables are the only source of warnings
+        let warnings = if opt.debug == DebugInfo::LineTables {
+            match fs::read_to_string(self.dir.join(format!("{}.warn", key))) {
+                Ok(w) => w.lines().map(String::from).collect(),
+                Err(_) => return None,
+            }
+        } else {
+            Vec::new()
+        };
+        Some(Entry {
+            object,
+            listing: if listing { Some(listing_path) } else { None },
+            externs,
+            renamed,
+            exported,
+            warnings,
+        })
+    }
+
+    /// Store the object (and listing, Rust declarations, symbols and warnings) built for the key
+    pub fn store(&self, key: &str, object: &Path, listing: Option<&Path>, emitted: &Emitted)
+    -> io::Result<Entry> {
+        let Emitted { ref externs, ref renamed, ref exported, ref warnings } = *emitted;
+        fs::create_dir_all(&self.dir)?;
+        let listing = match listing {
+            Some(l) => Some(self.store_file(&format!("{}.s", key), |p| fs::copy(l, p))?),
+            None => None
+        };
+        if let Some(ref e) = *externs {
+            self.store_file(&format!("{}.rs", key), |p| fs::write(p, e))?;
+        }
+        // Renamed symbols never contain tabs or newlines
+        let symbols = renamed.iter().map(|(old, new)| format!("{}\t{}\n", old, new))
+                             .collect::<String>();
+        self.store_file(&format!("{}.sym", key), |p| fs::write(p, symbols))?;
+        let exports = exported.iter().map(|e| format!("{}\n", e)).collect::<String>();
+        self.store_file(&format!("{}.exp", key), |p| fs::write(p, exports))?;
+        // Warnings span a single line
+        let warns = warnings.iter().map(|w| format!("{}\n", w)).collect::<String>();
+        self.store_file(&format!("{}.warn", key), |p| fs::write(p, warns))?;
+        // The object is stored last, its presence marks the entry complete
+        Ok(Entry {
+            object: self.store_file(&format!("{}.o", key), |p| fs::copy(object, p))?,
+            listing,
+            externs: externs.clone(),
+            renamed: renamed.clone(),
+            exported: exported.clone(),
+            warnings: warnings.clone(),
+        })
+    }
+
+    fn store_file<F, T>(&self, name: &str, write: F) -> io::Result<PathBuf>
+    where F: FnOnce(&Path) -> io::Result<T> {
+        // Write then rename, so that concurrent builds never observe a partially written file
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let partial = self.dir.join(format!("{}.{}-{}.partial", name, process::id(),
+                                            NEXT.fetch_add(1, Ordering::SeqCst)));
+        let to = self.dir.join(name);
+        write(&partial)?;
+        fs::rename(&partial, &to)?;
+        Ok(to)
+    }
+}
@@ -0,0 +1,219 @@
+//! Line tables mapping machine code back to the textual IR
+use std::ffi::CString;
+use std::path::Path;
+
+use libc;
+
+use ffi::*;
+
+/// A function defined by textual IR
+pub struct Function {
+    pub name: String,
+    /// Line of the `define`
+    pub line: usize,
+    /// Line and column of every instruction, in order
+    pub instructions: Vec<(usize, usize)>,
+}
+
+/// Replace the debug information of the module with line tables pointing at `source`, the
+/// textual IR the module was parsed from
+///
+/// `file` is the name the line tables refer to the source by, relative to `directory`. Functions
+/// which cannot be mapped to their lines are reported in `warnings`.
+pub unsafe fn line_tables(module: &Module, source: &str, file: &Path, directory: &Path,
+                          warnings: &mut Vec<String>) {
+    LLVMStripModuleDebugInfo(module.0);
+    let ctx = LLVMGetModuleContext(module.0);
+    let version = LLVMConstInt(LLVMInt32TypeInContext(ctx),
+                               LLVMDebugMetadataVersion() as libc::c_ulonglong, LLVMFalse);
+    let key = "Debug Info Version";
+    LLVMAddModuleFlag(module.0, LLVMModuleFlagBehaviorWarning,
+                      key.as_ptr() as *const libc::c_char, key.len() as libc::size_t,
+                      LLVMValueAsMetadata(version));
+
+    let builder = DIBuilder(LLVMCreateDIBuilder(module.0));
+    let input = file;
+    let file = file.to_string_lossy();
+    let directory = directory.to_string_lossy();
+    let file = LLVMDIBuilderCreateFile(builder.0, file.as_ptr() as *const libc::c_char,
+                                       file.len() as libc::size_t,
+                                       directory.as_ptr() as *const libc::c_char,
+                                       directory.len() as libc::size_t);
+    let producer = concat!("llvm_build_utils ", env!("CARGO_PKG_VERSION"));
+    let empty = "".as_ptr() as *const libc::c_char;
+    LLVMDIBuilderCreateCompileUnit(builder.0, LLVMDWARFSourceLanguageC, file,
+                                   producer.as_ptr() as *const libc::c_char,
+                                   producer.len() as libc::size_t, LLVMFalse, empty, 0, 0,
+                                   empty, 0, LLVMDWARFEmissionLineTablesOnly, 0, LLVMFalse,
+                                   LLVMFalse, empty, 0, empty, 0);
+    let ty = LLVMDIBuilderCreateSubroutineType(builder.0, file, ::std::ptr::null_mut(), 0, 0);
+    for function in parse(source) {
+        let value = match CString::new(function.name.clone()) {
+            Ok(name) => LLVMGetNamedFunction(module.0, name.as_ptr()),
+            Err(_) => continue,
+        };
+        if value.is_null() || LLVMIsDeclaration(value) == LLVMTrue {
+            continue;
+        }
+        let linkage = LLVMGetLinkage(value);
+        let local = linkage == LLVMInternalLinkage || linkage == LLVMPrivateLinkage;
+        let name = function.name.as_ptr() as *const libc::c_char;
+        let len = function.name.len() as libc::size_t;
+        let line = function.line as libc::c_uint;
+        let subprogram = LLVMDIBuilderCreateFunction(builder.0, file, name, len, name, len, file,
+                                                     line, ty, local as LLVMBool, LLVMTrue, line,
+                                                     0, LLVMFalse);
+        LLVMSetSubprogram(value, subprogram);
+        let instructions = instructions(value);
+        // Should the IR have been upgraded while parsing, the instructions no longer match the
+        // text, so the whole function is attributed to its `define`
+        let lines = if instructions.len() == function.instructions.len() {
+            function.instructions
+        } else {
+            warnings.push(format!("the line tables of `{}` in {} point at its `define` only: the \
+                                   instructions LLVM parsed do not match the text",
+                                  function.name, input.display()));
+            vec![(function.line, 1); instructions.len()]
+        };
+        for (instruction, (line, column)) in instructions.into_iter().zip(lines) {
+            let location = LLVMDIBuilderCreateDebugLocation(ctx, line as libc::c_uint,
+                                                            column as libc::c_uint, subprogram,
+                                                            ::std::ptr::null_mut());
+            LLVMInstructionSetDebugLoc(instruction, location);
+        }
+    }
+    LLVMDIBuilderFinalize(builder.0);
+}
+
+/// The instructions of the function, in order
+unsafe fn instructions(function: LLVMValueRef) -> Vec<LLVMValueRef> {
+    let mut ret = Vec::new();
+    let mut block = LLVMGetFirstBasicBlock(function);
+    while !block.is_null() {
+        let mut instruction = LLVMGetFirstInstruction(block);
+        while !instruction.is_null() {
+            ret.push(instruction);
+            instruction = LLVMGetNextInstruction(instruction);
+        }
+        block = LLVMGetNextBasicBlock(block);
+    }
+    ret
+}
+
+/// Find the functions and the lines of their instructions in textual IR
+///
+/// Every line in a function body which is not blank, a label or a debug intrinsic starts an
+/// instruction, unless it continues an instruction with unbalanced brackets, such as `switch`.
+pub fn parse(source: &str) -> Vec<Function> {
+    let mut ret = Vec::new();
+    let mut current: Option<(Function, bool)> = None;
+    let mut depth = 0;
+    for (idx, line) in source.lines().enumerate() {
+        let code = strip_comment(line);
+        let trimmed = code.trim();
+        let (mut function, in_body) = match current.take() {
+            Some(c) => c,
+            None => {
+                if trimmed.starts_with("define ") {
+                    if let Some(name) = function_name(trimmed) {
+                        let function = Function {
+                            name,
+                            line: idx + 1,
+                            instructions: Vec::new()
+                        };
+                        depth = 0;
+                        if trimmed.ends_with('}') {
+                            // The whole function on a single line
+                            ret.push(function);
+                        } else {
+                            current = Some((function, trimmed.ends_with('{')));
+                        }
+                    }
+                }
+                continue;
+            }
+        };
+        if !in_body {
+            current = Some((function, trimmed.ends_with('{')));
+            continue;
+        }
+        if depth == 0 && trimmed == "}" {
+            ret.push(function);
+            continue;
+        }
+        let skipped = trimmed.is_empty() || (depth == 0 && trimmed.ends_with(':')) ||
+                      trimmed.starts_with("#dbg_") || trimmed.contains("@llvm.dbg.");
+        if !skipped {
+            if depth == 0 {
+                let column = code.len() - code.trim_start().len() + 1;
+                function.instructions.push((idx + 1, column));
+            }
+            depth += bracket_balance(trimmed);
+        }
+        current = Some((function, true));
+    }
+    ret
+}
+
+/// The line without its comment, if any
+pub fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// How many more brackets the line opens than it closes
+fn bracket_balance(line: &str) -> isize {
+    let mut quoted = false;
+    let mut balance = 0;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' | '[' | '{' if !quoted => balance += 1,
+            ')' | ']' | '}' if !quoted => balance -= 1,
+            _ => {}
+        }
+    }
+    balance
+}
+
+/// Name of the function defined on the line, e.g. `foo` for `define i32 @foo(i32 %a) {`
+fn function_name(line: &str) -> Option<String> {
+    let rest = match line.find('@') {
+        Some(at) => &line[at + 1..],
+        None => return None,
+    };
+    if let Some(rest) = rest.strip_prefix('"') {
+        // Quoted names escape bytes as `\XX`
+        let quoted = match rest.find('"') {
+            Some(end) => &rest[..end],
+            None => return None,
+        };
+        let mut bytes = Vec::new();
+        let mut idx = 0;
+        while idx < quoted.len() {
+            let escaped = quoted.get(idx + 1..idx + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+            match escaped {
+                Some(b) if quoted.as_bytes()[idx] == b'\\' => {
+                    bytes.push(b);
+                    idx += 3;
+                }
+                _ => {
+                    bytes.push(quoted.as_bytes()[idx]);
+                    idx += 1;
+                }
+            }
+        }
+        String::from_utf8(bytes).ok()
+    } else {
+        let name = rest.split(|c: char| !c.is_ascii_alphanumeric() && !"-$._".contains(c))
+                       .next().unwrap_or("");
+        if name.is_empty() { None } else { Some(String::from(name)) }
+    }
+}
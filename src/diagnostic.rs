@@ -0,0 +1,158 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use debuginfo;
+
+/// A problem with an input, located in its source where possible
+///
+/// Renders in a manner similar to compiler diagnostics:
+///
+/// ```text
+/// error: expected instruction opcode
+///   --> tests/invalid_parse.ll:2:5
+///   |
+/// 2 |     frobnicate i64 %a
+///   |     ^
+/// ```
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// The input this diagnostic is about
+    pub input: PathBuf,
+    /// 1-based line in the input, if known
+    pub line: Option<usize>,
+    /// 1-based column in the input, if known
+    pub column: Option<usize>,
+    /// Primary message
+    pub message: String,
+    /// The source line `line` refers to, if known
+    pub source_line: Option<String>,
+    /// Name of the function which failed verification, if known
+    pub function: Option<String>,
+    /// Additional lines of the message (e.g. offending instructions reported by the verifier)
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    fn new(input: &Path, message: String) -> Diagnostic {
+        Diagnostic {
+            input: input.to_path_buf(),
+            line: None,
+            column: None,
+            message,
+            source_line: None,
+            function: None,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Construct the diagnostic out of a message produced by `LLVMParseIRInContext`
+    ///
+    /// The message for textual IR has format `<path>:<line>:<col>: error: <message>`, followed
+    /// by the offending source line and a caret. Messages for bytecode carry no location.
+    pub fn from_parse_error(input: &Path, message: &str) -> Diagnostic {
+        let mut lines = message.lines();
+        let first = lines.next().unwrap_or("");
+        let (location, text) = match first.find("error: ") {
+            Some(idx) => (&first[..idx], &first[idx + "error: ".len()..]),
+            None => ("", first),
+        };
+        let mut diag = Diagnostic::new(input, String::from(text.trim()));
+        let mut location = location.trim().trim_matches(':').rsplitn(3, ':');
+        let column = location.next().and_then(|c| c.parse().ok());
+        let line = location.next().and_then(|l| l.parse().ok());
+        if let (Some(line), Some(column)) = (line, column) {
+            diag.line = Some(line);
+            diag.column = Some(column);
+            diag.source_line = lines.next().map(String::from);
+        }
+        diag
+    }
+
+    /// Construct the diagnostic out of a message produced by `LLVMVerifyModule`
+    ///
+    /// `function` is the name of the function which failed verification and `source` is the
+    /// textual IR of the input, if available. These are used to point at the instruction the
+    /// verifier complains about or, failing to find it, at the definition of the function.
+    pub fn from_verify_error(input: &Path, message: &str, function: Option<String>,
+                             source: Option<&str>) -> Diagnostic {
+        let mut lines = message.lines().filter(|l| !l.trim().is_empty());
+        let mut diag = Diagnostic::new(input, String::from(lines.next().unwrap_or("").trim()));
+        diag.notes = lines.map(String::from).collect();
+        if let (Some(name), Some(source)) = (function.as_ref(), source) {
+            let found = find_instruction(source, name, &diag.notes)
+                        .or_else(|| find_definition(source, name));
+            if let Some((line, column, text)) = found {
+                diag.line = Some(line);
+                diag.column = Some(column);
+                diag.source_line = Some(String::from(text));
+            }
+        }
+        diag.function = function;
+        diag
+    }
+}
+
+/// Find the line and column of the instruction of function `name` the verifier printed last in
+/// `notes`, the offending instruction following the values it involves
+///
+/// The instructions are compared with their whitespace collapsed. Should LLVM print one
+/// differently than it is written, e.g. with the alignment it inferred, the instruction
+/// defining the same value is taken instead.
+fn find_instruction<'a>(source: &'a str, name: &str, notes: &[String])
+-> Option<(usize, usize, &'a str)> {
+    // The value the instruction defines, e.g. `%x` for `%x = add i32 %a, 1`
+    fn defined(text: &str) -> Option<&str> {
+        text.find(" = ").map(|end| &text[..end]).filter(|value| value.starts_with('%'))
+    }
+    let normalise = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let function = debuginfo::parse(source).into_iter().find(|f| f.name == name)?;
+    let lines = source.lines().collect::<Vec<_>>();
+    let instructions = function.instructions.iter().map(|&(line, column)| {
+        (line, column, normalise(debuginfo::strip_comment(lines[line - 1])))
+    }).collect::<Vec<_>>();
+    let found = notes.iter().rev().filter_map(|note| {
+        let note = normalise(note);
+        instructions.iter().find(|i| i.2 == note).or_else(|| {
+            let value = defined(&note)?;
+            instructions.iter().find(|i| defined(&i.2) == Some(value))
+        })
+    }).next();
+    found.map(|&(line, column, _)| (line, column, lines[line - 1]))
+}
+
+/// Find the line and column at which function `name` is defined in textual IR
+fn find_definition<'a>(source: &'a str, name: &str) -> Option<(usize, usize, &'a str)> {
+    let plain = format!("@{}(", name);
+    let quoted = format!("@\"{}\"(", name);
+    source.lines().enumerate().filter_map(|(idx, line)| {
+        if !line.trim().starts_with("define") {
+            return None;
+        }
+        line.find(&plain).or_else(|| line.find(&quoted)).map(|col| (idx + 1, col + 1, line))
+    }).next()
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+        let gutter = self.line.map(|l| l.to_string().len()).unwrap_or(0);
+        let pad = " ".repeat(gutter);
+        write!(f, "{} --> {}", pad, self.input.display())?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, ":{}:{}", line, column)?;
+            if let Some(ref source) = self.source_line {
+                let caret = source.chars().take(column.saturating_sub(1))
+                                  .map(|c| if c == '\t' { '\t' } else { ' ' })
+                                  .collect::<String>();
+                write!(f, "\n{} |\n{} | {}\n{} | {}^", pad, line, source, pad, caret)?;
+            }
+        }
+        if let Some(ref function) = self.function {
+            write!(f, "\n{} = in function `{}`", pad, function)?;
+        }
+        for note in &self.notes {
+            write!(f, "\n{} = note: {}", pad, note)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! Listings produced by disassembling object files
+use std::ffi::CStr;
+
+use libc;
+
+use ffi::*;
+
+/// A symbol or relocation at an offset into a section
+type Mark = (u64, String);
+
+/// Disassemble the code sections of an x86 object file in Intel syntax
+///
+/// The disassembler picks the syntax on its own, unlike the code generator, which follows the
+/// process-wide `-x86-asm-syntax` option. The listing has a label for every symbol defined in the
+/// code and names the symbols the instructions refer to, but none of the directives of an
+/// assembly listing.
+pub unsafe fn intel_listing(object: &MemoryBuffer, triple: &CStr, cpu: &CStr, features: &CStr)
+-> Result<String, String> {
+    let mut msg = ::std::ptr::null_mut();
+    let binary = LLVMCreateBinary(object.0, ::std::ptr::null_mut(), &mut msg);
+    if binary.is_null() {
+        let ret = String::from_utf8_lossy(CStr::from_ptr(msg).to_bytes()).into_owned();
+        LLVMDisposeMessage(msg);
+        return Err(ret);
+    }
+    let binary = Binary(binary);
+    let disasm = LLVMCreateDisasmCPUFeatures(triple.as_ptr(), cpu.as_ptr(), features.as_ptr(),
+                                             ::std::ptr::null_mut(), 0, ::std::ptr::null(),
+                                             ::std::ptr::null());
+    if disasm.is_null() {
+        return Err(String::from("the LLVM in use has no disassembler for the target"));
+    }
+    let disasm = Disassembler(disasm);
+    if LLVMSetDisasmOptions(disasm.0, LLVMDisassembler_Option_AsmPrinterVariant) == 0 {
+        return Err(String::from("the disassembler does not support Intel syntax"));
+    }
+
+    let mut listing = String::from("\t.intel_syntax noprefix\n");
+    let section = LLVMObjectFileCopySectionIterator(binary.0);
+    while LLVMObjectFileIsSectionIteratorAtEnd(binary.0, section) == LLVMFalse {
+        let name = string(LLVMGetSectionName(section));
+        let size = LLVMGetSectionSize(section);
+        if size != 0 && (name.starts_with(".text") || name == "__text") {
+            let contents = LLVMGetSectionContents(section) as *const u8;
+            let contents = ::std::slice::from_raw_parts(contents, size as usize);
+            let labels = labels(&binary, section, &name);
+            let relocations = relocations(&binary, &name);
+            listing.push_str(&format!("\n# section {}\n", name));
+            disassemble(&disasm, contents, &labels, &relocations, &mut listing);
+        }
+        LLVMMoveToNextSection(section);
+    }
+    LLVMDisposeSectionIterator(section);
+    Ok(listing)
+}
+
+/// Disassemble the contents of a section into the listing
+unsafe fn disassemble(disasm: &Disassembler, contents: &[u8], labels: &[Mark],
+                      relocations: &[Mark], listing: &mut String) {
+    let mut text = [0 as libc::c_char; 256];
+    let mut offset = 0;
+    while offset < contents.len() {
+        let pc = offset as u64;
+        for (_, label) in labels.iter().filter(|l| l.0 == pc) {
+            listing.push_str(&format!("{}:\n", label));
+        }
+        let rest = &contents[offset..];
+        let len = LLVMDisasmInstruction(disasm.0, rest.as_ptr() as *mut u8, rest.len() as u64, pc,
+                                        text.as_mut_ptr(), text.len() as libc::size_t);
+        if len == 0 {
+            listing.push_str(&format!("\t.byte\t{:#04x}\n", rest[0]));
+            offset += 1;
+            continue;
+        }
+        listing.push_str(&string(text.as_ptr()));
+        let end = pc + len as u64;
+        for (_, symbol) in relocations.iter().filter(|r| r.0 >= pc && r.0 < end) {
+            listing.push_str(&format!("\t# {}", symbol));
+        }
+        listing.push('\n');
+        offset += len as usize;
+    }
+}
+
+/// Symbols defined in the section, by their offset into it
+///
+/// The symbols standing for the section itself are left out.
+unsafe fn labels(binary: &Binary, section: LLVMSectionIteratorRef, name: &str) -> Vec<Mark> {
+    let base = LLVMGetSectionAddress(section);
+    let mut ret = Vec::new();
+    let symbol = LLVMObjectFileCopySymbolIterator(binary.0);
+    while LLVMObjectFileIsSymbolIteratorAtEnd(binary.0, symbol) == LLVMFalse {
+        let label = string(LLVMGetSymbolName(symbol));
+        if !label.is_empty() && label != name &&
+           LLVMGetSectionContainsSymbol(section, symbol) == LLVMTrue {
+            ret.push((LLVMGetSymbolAddress(symbol).wrapping_sub(base), label));
+        }
+        LLVMMoveToNextSymbol(symbol);
+    }
+    LLVMDisposeSymbolIterator(symbol);
+    ret.sort();
+    ret
+}
+
+/// Symbols the relocations of the section named `name` refer to, by the offset of the relocation
+///
+/// ELF keeps the relocations in sections of their own, named `.rel<name>` or `.rela<name>`.
+unsafe fn relocations(binary: &Binary, name: &str) -> Vec<Mark> {
+    let names = [String::from(name), format!(".rel{}", name), format!(".rela{}", name)];
+    let mut ret = Vec::new();
+    let section = LLVMObjectFileCopySectionIterator(binary.0);
+    while LLVMObjectFileIsSectionIteratorAtEnd(binary.0, section) == LLVMFalse {
+        if names.contains(&string(LLVMGetSectionName(section))) {
+            let relocation = LLVMGetRelocations(section);
+            while LLVMIsRelocationIteratorAtEnd(section, relocation) == LLVMFalse {
+                // Relocations relative to a section rather than a symbol have no symbol
+                let symbol = LLVMGetRelocationSymbol(relocation);
+                if LLVMObjectFileIsSymbolIteratorAtEnd(binary.0, symbol) == LLVMFalse {
+                    let symbol_name = string(LLVMGetSymbolName(symbol));
+                    if !symbol_name.is_empty() {
+                        ret.push((LLVMGetRelocationOffset(relocation), symbol_name));
+                    }
+                }
+                LLVMDisposeSymbolIterator(symbol);
+                LLVMMoveToNextRelocation(relocation);
+            }
+            LLVMDisposeRelocationIterator(relocation);
+        }
+        LLVMMoveToNextSection(section);
+    }
+    LLVMDisposeSectionIterator(section);
+    ret
+}
+
+unsafe fn string(ptr: *const libc::c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        String::from_utf8_lossy(CStr::from_ptr(ptr).to_bytes()).into_owned()
+    }
+}
@@ -0,0 +1,445 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use BuildOptions;
+use diagnostic::Diagnostic;
+
+/// An error which occurred while building an archive
+///
+/// Every variant carries enough information to tell what went wrong without parsing the message:
+/// the offending input or output path, the `BuildOptions` in effect and the raw message reported
+/// by LLVM, where applicable.
+#[derive(Debug)]
+pub enum BuildError {
+    /// The requested archive filename is not acceptable
+    ///
+    /// Archive filenames must have a `.a` extension and a `lib` prefix.
+    ArchiveName {
+        /// The archive filename as given
+        archive: PathBuf,
+        /// What is wrong with the filename
+        reason: &'static str,
+    },
+    /// A path cannot be passed to LLVM because it is not UTF-8 or contains null bytes
+    InvalidPath {
+        /// The offending path
+        path: PathBuf,
+        /// What is wrong with the path
+        reason: &'static str,
+    },
+    /// A value in `BuildOptions` cannot be passed to LLVM because it contains null bytes
+    InvalidOptions {
+        /// The input being built
+        input: PathBuf,
+        /// The options in effect
+        options: Box<BuildOptions>,
+        /// Name of the offending field
+        field: &'static str,
+    },
+    /// The target triple is malformed or names an unknown architecture
+    InvalidTriple {
+        /// The input being built, if the triple came from its `BuildOptions`
+        input: Option<PathBuf>,
+        /// The triple as given
+        triple: String,
+        /// What is wrong with the triple
+        reason: &'static str,
+    },
+    /// The target features are malformed or conflict with each other
+    InvalidFeatures {
+        /// The input being built, if the features came from its `BuildOptions`
+        input: Option<PathBuf>,
+        /// The features as given
+        features: String,
+        /// The offending feature
+        feature: String,
+        /// What is wrong with the feature
+        reason: &'static str,
+    },
+    /// An archive member name is not acceptable
+    MemberName {
+        /// The input built into the member
+        input: PathBuf,
+        /// The member name as given in `BuildOptions::ar_section_name`
+        name: String,
+        /// What is wrong with the name
+        reason: &'static str,
+    },
+    /// LLVM context could not be created
+    Context,
+    /// The input file does not exist or could not be read
+    Read {
+        /// The input which could not be read
+        input: PathBuf,
+        /// The error which occurred while reading the input
+        error: io::Error,
+    },
+    /// The input is not well formed LLVM-IR or LLVM bytecode
+    Parse {
+        /// The input which could not be parsed
+        input: PathBuf,
+        /// Message reported by LLVM
+        message: String,
+        /// Location of the problem in the input
+        diagnostic: Box<Diagnostic>,
+    },
+    /// The input has been parsed, but the module did not pass verification
+    Verify {
+        /// The input which is not valid
+        input: PathBuf,
+        /// Message reported by LLVM
+        message: String,
+        /// Location of the problem in the input
+        diagnostic: Box<Diagnostic>,
+    },
+    /// The inputs could not be linked together
+    Link {
+        /// The input which could not be linked in
+        input: PathBuf,
+        /// The symbol defined by more than one input, if that is the problem
+        symbol: Option<String>,
+        /// Message reported by LLVM
+        message: String,
+    },
+    /// A symbol cannot be renamed as requested by `BuildOptions::symbol_prefix` or
+    /// `BuildOptions::symbol_renames`
+    Rename {
+        /// The input defining or referring to the symbol
+        input: PathBuf,
+        /// The original name of the symbol
+        symbol: String,
+        /// The name the symbol was to be renamed to
+        renamed: String,
+        /// Why the symbol cannot be renamed
+        reason: &'static str,
+    },
+    /// A symbol in `BuildOptions::exports` is not defined by any input of the archive
+    UndefinedExport {
+        /// The input built with the exports
+        input: PathBuf,
+        /// The undefined symbol
+        symbol: String,
+    },
+    /// The input cannot be built for several sets of target features
+    Multiversion {
+        /// The input to build
+        input: PathBuf,
+        /// The offending set of features, empty if the problem is not specific to one
+        variant: String,
+        /// What prevents building the variants
+        reason: &'static str,
+    },
+    /// LLVM does not know about the target requested in `BuildOptions::triple`
+    UnsupportedTarget {
+        /// The input being built
+        input: PathBuf,
+        /// The options in effect
+        options: Box<BuildOptions>,
+        /// Message reported by LLVM
+        message: String,
+    },
+    /// The backend for the target requested in `BuildOptions::triple` is not a part of the LLVM
+    /// in use
+    ///
+    /// Only a missing backend is reported this way. When an available backend cannot generate
+    /// code for the input, e.g. an `i128` return value on BPF, LLVM aborts the process instead.
+    TargetUnavailable {
+        /// The input being built
+        input: PathBuf,
+        /// The options in effect
+        options: Box<BuildOptions>,
+        /// Name of the missing backend, e.g. `RISCV`
+        backend: &'static str,
+    },
+    /// The backend in use cannot do something the build asks for
+    Unsupported {
+        /// The input being built
+        input: PathBuf,
+        /// What the backend cannot do
+        feature: &'static str,
+    },
+    /// `BuildOptions::cpu` or `BuildOptions::attr` names a CPU or a feature unknown to the target
+    Unrecognised {
+        /// The input being built
+        input: PathBuf,
+        /// The options in effect
+        options: Box<BuildOptions>,
+        /// Name of the offending field, `cpu` or `attr`
+        field: &'static str,
+        /// The unknown CPU or feature
+        name: String,
+        /// The known CPU or feature closest in spelling, if any
+        suggestion: Option<String>,
+    },
+    /// The CPUs and features of the target could not be listed
+    ///
+    /// They are listed by `llc`, which must be of the same LLVM version as the one in use.
+    QuerySubtargets {
+        /// The target triple
+        triple: String,
+        /// What went wrong
+        message: String,
+    },
+    /// Target machine could not be created, likely because of invalid `BuildOptions`
+    TargetMachine {
+        /// The input being built
+        input: PathBuf,
+        /// The options in effect
+        options: Box<BuildOptions>,
+    },
+    /// The IR optimisation pipeline is invalid or failed to run
+    Passes {
+        /// The input being built
+        input: PathBuf,
+        /// The options in effect
+        options: Box<BuildOptions>,
+        /// Message reported by LLVM
+        message: String,
+    },
+    /// A temporary file for the object code could not be created
+    TempFile {
+        /// The input being built
+        input: PathBuf,
+        /// The underlying IO error
+        error: io::Error,
+    },
+    /// Machine code could not be generated
+    Emit {
+        /// The input being built
+        input: PathBuf,
+        /// The options in effect
+        options: Box<BuildOptions>,
+        /// Message reported by LLVM
+        message: String,
+    },
+    /// The assembly listing could not be written
+    Listing {
+        /// Path to the listing which could not be written
+        path: PathBuf,
+        /// The underlying IO error
+        error: io::Error,
+    },
+    /// A function defined by the input has no C-ABI-safe Rust equivalent to declare it with
+    UnsupportedSignature {
+        /// The input defining the function
+        input: PathBuf,
+        /// Name of the function
+        function: String,
+        /// What has no Rust equivalent
+        reason: String,
+    },
+    /// The Rust declarations or dispatchers could not be written
+    ExternDecls {
+        /// Path to the declarations which could not be written
+        path: PathBuf,
+        /// The underlying IO error
+        error: io::Error,
+    },
+    /// An existing archive could not be read
+    ReadArchive {
+        /// Path to the archive which could not be read
+        archive: PathBuf,
+        /// Message reported by LLVM
+        message: String,
+    },
+    /// The archive could not be written
+    WriteArchive {
+        /// Path to the archive which could not be written
+        archive: PathBuf,
+        /// Message reported by LLVM
+        message: String,
+    },
+    /// The directory to put an archive into could not be created
+    OutputDir {
+        /// Path to the directory which could not be created
+        path: PathBuf,
+        /// The underlying IO error
+        error: io::Error,
+    },
+    /// The version of a tool the backend runs could not be determined
+    Version {
+        /// Path to the tool
+        tool: PathBuf,
+        /// What went wrong
+        message: String,
+    },
+}
+
+impl BuildError {
+    /// The input this error relates to, if any
+    pub fn input(&self) -> Option<&Path> {
+        match *self {
+            BuildError::InvalidOptions { ref input, .. } |
+            BuildError::Read { ref input, .. } |
+            BuildError::Parse { ref input, .. } |
+            BuildError::Verify { ref input, .. } |
+            BuildError::Link { ref input, .. } |
+            BuildError::Rename { ref input, .. } |
+            BuildError::UndefinedExport { ref input, .. } |
+            BuildError::Multiversion { ref input, .. } |
+            BuildError::UnsupportedTarget { ref input, .. } |
+            BuildError::TargetUnavailable { ref input, .. } |
+            BuildError::Unsupported { ref input, .. } |
+            BuildError::Unrecognised { ref input, .. } |
+            BuildError::TargetMachine { ref input, .. } |
+            BuildError::Passes { ref input, .. } |
+            BuildError::TempFile { ref input, .. } |
+            BuildError::Emit { ref input, .. } |
+            BuildError::MemberName { ref input, .. } |
+            BuildError::UnsupportedSignature { ref input, .. } => Some(input),
+            BuildError::InvalidTriple { ref input, .. } |
+            BuildError::InvalidFeatures { ref input, .. } => input.as_ref().map(|i| i.as_path()),
+            _ => None,
+        }
+    }
+
+    /// The `BuildOptions` in effect when this error occurred, if any
+    pub fn options(&self) -> Option<&BuildOptions> {
+        match *self {
+            BuildError::InvalidOptions { ref options, .. } |
+            BuildError::UnsupportedTarget { ref options, .. } |
+            BuildError::TargetUnavailable { ref options, .. } |
+            BuildError::Unrecognised { ref options, .. } |
+            BuildError::TargetMachine { ref options, .. } |
+            BuildError::Passes { ref options, .. } |
+            BuildError::Emit { ref options, .. } => Some(options),
+            _ => None,
+        }
+    }
+
+    /// Location of the problem within the input, if any
+    pub fn diagnostic(&self) -> Option<&Diagnostic> {
+        match *self {
+            BuildError::Parse { ref diagnostic, .. } |
+            BuildError::Verify { ref diagnostic, .. } => Some(diagnostic),
+            _ => None,
+        }
+    }
+
+    /// The raw message reported by LLVM, if any
+    pub fn llvm_message(&self) -> Option<&str> {
+        match *self {
+            BuildError::Parse { ref message, .. } |
+            BuildError::Verify { ref message, .. } |
+            BuildError::Link { ref message, .. } |
+            BuildError::UnsupportedTarget { ref message, .. } |
+            BuildError::Passes { ref message, .. } |
+            BuildError::Emit { ref message, .. } |
+            BuildError::ReadArchive { ref message, .. } |
+            BuildError::WriteArchive { ref message, .. } => Some(message),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::ArchiveName { ref archive, reason } =>
+                write!(f, "invalid archive filename {}: {}", archive.display(), reason),
+            BuildError::InvalidPath { ref path, reason } =>
+                write!(f, "invalid path {}: {}", path.display(), reason),
+            BuildError::InvalidOptions { ref input, field, .. } =>
+                write!(f, "could not build {}: `{}` contains null bytes", input.display(), field),
+            BuildError::InvalidTriple { input: Some(ref input), ref triple, reason } =>
+                write!(f, "could not build {}: invalid target triple {:?}: {}",
+                       input.display(), triple, reason),
+            BuildError::InvalidTriple { input: None, ref triple, reason } =>
+                write!(f, "invalid target triple {:?}: {}", triple, reason),
+            BuildError::InvalidFeatures { input: Some(ref input), ref features, ref feature,
+                                          reason } =>
+                write!(f, "could not build {}: invalid target features {:?}: `{}` {}",
+                       input.display(), features, feature, reason),
+            BuildError::InvalidFeatures { input: None, ref features, ref feature, reason } =>
+                write!(f, "invalid target features {:?}: `{}` {}", features, feature, reason),
+            BuildError::MemberName { ref input, ref name, reason } =>
+                write!(f, "invalid archive member name {:?} for {}: {}",
+                       name, input.display(), reason),
+            BuildError::Context =>
+                write!(f, "could not create the LLVM context"),
+            BuildError::Read { ref input, ref error } =>
+                write!(f, "could not open input file {}: {}", input.display(), error),
+            BuildError::Parse { ref diagnostic, .. } |
+            BuildError::Verify { ref diagnostic, .. } => diagnostic.fmt(f),
+            BuildError::Link { ref input, symbol: Some(ref symbol), .. } =>
+                write!(f, "could not link {}: symbol `{}` is defined by more than one input",
+                       input.display(), symbol),
+            BuildError::Link { ref input, symbol: None, ref message } =>
+                write!(f, "could not link {}: {}", input.display(), message),
+            BuildError::Rename { ref input, ref symbol, ref renamed, reason } =>
+                write!(f, "could not rename symbol `{}` of {} to `{}`: {}",
+                       symbol, input.display(), renamed, reason),
+            BuildError::UndefinedExport { ref input, ref symbol } =>
+                write!(f, "could not export `{}` as listed for {}: no input defines the symbol",
+                       symbol, input.display()),
+            BuildError::Multiversion { ref input, ref variant, reason } if variant.is_empty() =>
+                write!(f, "could not build variants of {}: {}", input.display(), reason),
+            BuildError::Multiversion { ref input, ref variant, reason } =>
+                write!(f, "could not build the {:?} variant of {}: {}",
+                       variant, input.display(), reason),
+            BuildError::UnsupportedTarget { ref input, ref options, ref message } =>
+                write!(f, "could not build {} for target {:?}: {}",
+                       input.display(), options.triple, message),
+            BuildError::TargetUnavailable { ref input, ref options, backend } =>
+                write!(f, "could not build {} for target {:?}: target not available, the {} \
+                           backend is not a part of the LLVM in use",
+                       input.display(), options.triple, backend),
+            BuildError::Unsupported { ref input, feature } =>
+                write!(f, "could not build {}: {} is not supported by the backend in use",
+                       input.display(), feature),
+            BuildError::Unrecognised { ref input, ref options, field, ref name,
+                                       ref suggestion } => {
+                write!(f, "could not build {}: `{}` is not a {} known to target {:?}",
+                       input.display(), name, if field == "cpu" { "CPU" } else { "feature" },
+                       options.triple)?;
+                match *suggestion {
+                    Some(ref s) => write!(f, ", did you mean `{}`?", s),
+                    None => Ok(()),
+                }
+            }
+            BuildError::QuerySubtargets { ref triple, ref message } =>
+                write!(f, "could not list the CPUs and features of target {:?}: {}", triple,
+                       message),
+            BuildError::TargetMachine { ref input, ref options } =>
+                write!(f, "could not create the target machine for {} (likely invalid \
+                           BuildOptions {:?})", input.display(), options),
+            BuildError::Passes { ref input, ref options, ref message } =>
+                write!(f, "could not optimise {} with pipeline {:?}: {}",
+                       input.display(), options.passes, message),
+            BuildError::TempFile { ref input, ref error } =>
+                write!(f, "could not create temp file for {}: {}", input.display(), error),
+            BuildError::Emit { ref input, ref message, .. } =>
+                write!(f, "could not generate object file for {}: {}", input.display(), message),
+            BuildError::Listing { ref path, ref error } =>
+                write!(f, "could not write assembly listing {}: {}", path.display(), error),
+            BuildError::UnsupportedSignature { ref input, ref function, ref reason } =>
+                write!(f, "could not declare function `{}` defined by {} in Rust: {}",
+                       function, input.display(), reason),
+            BuildError::ExternDecls { ref path, ref error } =>
+                write!(f, "could not write Rust declarations {}: {}", path.display(), error),
+            BuildError::ReadArchive { ref archive, ref message } =>
+                write!(f, "could not read archive {}: {}", archive.display(), message),
+            BuildError::WriteArchive { ref archive, ref message } =>
+                write!(f, "could not write archive {}: {}", archive.display(), message),
+            BuildError::OutputDir { ref path, ref error } =>
+                write!(f, "could not create output directory {}: {}", path.display(), error),
+            BuildError::Version { ref tool, ref message } =>
+                write!(f, "could not determine the version of {}: {}", tool.display(), message),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BuildError::Read { ref error, .. } |
+            BuildError::TempFile { ref error, .. } |
+            BuildError::Listing { ref error, .. } |
+            BuildError::ExternDecls { ref error, .. } |
+            BuildError::OutputDir { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Rust `extern` declarations of the functions defined by a module
+use std::ffi::CStr;
+use std::fmt::Write;
+
+use libc;
+
+use take_message;
+use ffi::*;
+
+/// Rust keywords, which cannot be used as identifiers
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// A function which cannot be declared in Rust
+pub struct Unsupported {
+    pub function: String,
+    pub reason: String,
+}
+
+/// The Rust signature of a function
+pub struct Signature {
+    /// Name of the symbol
+    pub name: String,
+    /// Name of the symbol turned into a Rust identifier
+    pub ident: String,
+    /// The calling convention, e.g. `C`
+    pub abi: &'static str,
+    /// Names and types of the parameters
+    pub params: Vec<(String, String)>,
+    /// Whether the function takes a variable number of arguments
+    pub variadic: bool,
+    /// The return type, if any
+    pub ret: Option<String>,
+}
+
+impl Signature {
+    /// The parameter list, e.g. `a: u64, b: u64`
+    pub fn params(&self) -> String {
+        let mut params = self.params.iter().map(|(n, t)| format!("{}: {}", n, t))
+                                       .collect::<Vec<_>>();
+        if self.variadic {
+            params.push(String::from("..."));
+        }
+        params.join(", ")
+    }
+
+    /// The return type as written after the parameters, e.g. ` -> u64`
+    pub fn ret(&self) -> String {
+        self.ret.as_ref().map(|r| format!(" -> {}", r)).unwrap_or_default()
+    }
+
+    /// The declaration within an `extern` block
+    pub fn declaration(&self) -> String {
+        let link_name = if self.ident != self.name {
+            format!("    #[link_name = {:?}]\n", self.name)
+        } else {
+            String::new()
+        };
+        format!("{}    pub fn {}({}){};\n", link_name, self.ident, self.params(), self.ret())
+    }
+}
+
+/// Signatures of the functions defined by a module
+pub struct Signatures {
+    pub functions: Vec<Signature>,
+    /// Definitions of the structures used by the signatures
+    pub structs: String,
+}
+
+/// Generate the Rust declarations of the externally visible functions defined by the module
+///
+/// Literal structures passed by value are declared as `#[repr(C)]` tuple structures named after
+/// the function, e.g. `foo_ret` for the return value of `foo`.
+pub unsafe fn declarations(module: &Module) -> Result<String, Unsupported> {
+    let signatures = signatures(module)?;
+    let mut blocks: Vec<(&'static str, String)> = Vec::new();
+    for signature in &signatures.functions {
+        let declaration = signature.declaration();
+        match blocks.iter_mut().find(|b| b.0 == signature.abi) {
+            Some(block) => block.1.push_str(&declaration),
+            None => blocks.push((signature.abi, declaration)),
+        }
+    }
+    let mut ret = signatures.structs;
+    for (abi, declarations) in blocks {
+        let _ = write!(ret, "extern \"{}\" {{\n{}}}\n", abi, declarations);
+    }
+    Ok(ret)
+}
+
+/// The Rust signatures of the externally visible functions defined by the module
+pub unsafe fn signatures(module: &Module) -> Result<Signatures, Unsupported> {
+    let triple = CStr::from_ptr(LLVMGetTarget(module.0)).to_string_lossy().into_owned();
+    let mut generator = Generator {
+        arch: String::from(triple.split('-').next().unwrap_or("")),
+        structs: String::new(),
+    };
+    let mut ret = Vec::new();
+    for function in functions(module.0) {
+        let linkage = LLVMGetLinkage(function);
+        if LLVMIsDeclaration(function) == LLVMTrue ||
+           linkage == LLVMInternalLinkage || linkage == LLVMPrivateLinkage {
+            continue;
+        }
+        let name = value_name(function);
+        let unsupported = |reason: String| Unsupported { function: name.clone(), reason };
+        let abi = abi_name(LLVMGetFunctionCallConv(function)).map_err(&unsupported)?;
+        let mut signature = generator.function(function, &name).map_err(&unsupported)?;
+        signature.abi = abi;
+        ret.push(signature);
+    }
+    Ok(Signatures { functions: ret, structs: generator.structs })
+}
+
+/// The Rust name of the calling convention
+fn abi_name(callconv: libc::c_uint) -> Result<&'static str, String> {
+    match callconv {
+        0 => Ok("C"),
+        64 => Ok("stdcall"),
+        65 => Ok("fastcall"),
+        67 => Ok("aapcs"),
+        78 => Ok("sysv64"),
+        79 => Ok("win64"),
+        _ => Err(format!("calling convention {} has no Rust equivalent", callconv)),
+    }
+}
+
+/// Turn a symbol name into a Rust identifier
+pub fn identifier(name: &str) -> String {
+    let mut ret = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                             .collect::<String>();
+    if ret.is_empty() || ret.starts_with(|c: char| c.is_ascii_digit()) {
+        ret.insert(0, '_');
+    }
+    if KEYWORDS.contains(&&ret[..]) {
+        ret.push('_');
+    }
+    ret
+}
+
+struct Generator {
+    arch: String,
+    /// Definitions of the structures used by the declarations so far
+    structs: String,
+}
+
+impl Generator {
+    unsafe fn function(&mut self, function: LLVMValueRef, name: &str)
+    -> Result<Signature, String> {
+        let ident = identifier(name);
+        let ty = LLVMGlobalGetValueType(function);
+        let mut params = vec![::std::ptr::null_mut(); LLVMCountParamTypes(ty) as usize];
+        LLVMGetParamTypes(ty, params.as_mut_ptr());
+        let mut names: Vec<String> = Vec::new();
+        let mut rendered = Vec::new();
+        for (idx, &param) in params.iter().enumerate() {
+            let signed = has_attribute(function, idx as libc::c_uint + 1, "signext");
+            let ty = self.rust_type(param, &format!("{}_arg{}", ident, idx), signed)?;
+            let param_name = value_name(LLVMGetParam(function, idx as libc::c_uint));
+            let param_name = if !param_name.is_empty() && identifier(&param_name) == param_name &&
+                                !names.contains(&param_name) {
+                param_name
+            } else {
+                format!("arg{}", idx)
+            };
+            rendered.push((param_name.clone(), ty));
+            names.push(param_name);
+        }
+        let variadic = LLVMIsFunctionVarArg(ty) == LLVMTrue;
+        if variadic && rendered.is_empty() {
+            return Err(String::from("variadic functions must have a parameter in Rust"));
+        }
+        let ret = LLVMGetReturnType(ty);
+        let ret = if LLVMGetTypeKind(ret) == LLVMVoidTypeKind {
+            None
+        } else {
+            let signed = has_attribute(function, LLVMAttributeReturnIndex, "signext");
+            Some(self.rust_type(ret, &format!("{}_ret", ident), signed)?)
+        };
+        Ok(Signature {
+            name: String::from(name),
+            ident,
+            abi: "C",
+            params: rendered,
+            variadic,
+            ret,
+        })
+    }
+
+    /// The Rust equivalent of the type, `name` is used to name the literal structures
+    unsafe fn rust_type(&mut self, ty: LLVMTypeRef, name: &str, signed: bool)
+    -> Result<String, String> {
+        let sign = if signed { "i" } else { "u" };
+        match LLVMGetTypeKind(ty) {
+            LLVMIntegerTypeKind => match LLVMGetIntTypeWidth(ty) {
+                1 => Ok(String::from("bool")),
+                w @ 8 | w @ 16 | w @ 32 | w @ 64 | w @ 128 => Ok(format!("{}{}", sign, w)),
+                w => Err(format!("{}-bit integers have no Rust equivalent", w)),
+            },
+            LLVMFloatTypeKind => Ok(String::from("f32")),
+            LLVMDoubleTypeKind => Ok(String::from("f64")),
+            LLVMPointerTypeKind => Ok(String::from("*mut ::std::os::raw::c_void")),
+            LLVMVectorTypeKind => self.vector_type(ty, signed),
+            LLVMStructTypeKind if LLVMIsLiteralStruct(ty) == LLVMTrue => {
+                let count = LLVMCountStructElementTypes(ty) as usize;
+                if count == 0 {
+                    return Err(String::from("empty structures have no C-ABI-safe equivalent"));
+                }
+                let mut fields = vec![::std::ptr::null_mut(); count];
+                LLVMGetStructElementTypes(ty, fields.as_mut_ptr());
+                let mut rendered = Vec::new();
+                for (idx, &field) in fields.iter().enumerate() {
+                    let field = self.rust_type(field, &format!("{}_{}", name, idx), false)?;
+                    rendered.push(format!("pub {}", field));
+                }
+                let repr = if LLVMIsPackedStruct(ty) == LLVMTrue { "C, packed" } else { "C" };
+                let _ = write!(self.structs,
+                               "#[repr({})]\n#[derive(Clone, Copy)]\n\
+                                #[allow(non_camel_case_types)]\npub struct {}({});\n",
+                               repr, name, rendered.join(", "));
+                Ok(String::from(name))
+            }
+            _ => Err(format!("type `{}` has no C-ABI-safe Rust equivalent", type_name(ty))),
+        }
+    }
+
+    /// The type from `std::arch` equivalent to the vector type
+    unsafe fn vector_type(&mut self, ty: LLVMTypeRef, signed: bool) -> Result<String, String> {
+        let element = LLVMGetElementType(ty);
+        let count = LLVMGetVectorSize(ty);
+        let (float, bits) = match LLVMGetTypeKind(element) {
+            LLVMIntegerTypeKind => (false, LLVMGetIntTypeWidth(element)),
+            LLVMFloatTypeKind => (true, 32),
+            LLVMDoubleTypeKind => (true, 64),
+            _ => return Err(format!("vector `{}` has no Rust equivalent", type_name(ty))),
+        };
+        let total = bits * count;
+        let unsupported = || format!("vector `{}` has no Rust equivalent on {}",
+                                     type_name(ty), self.arch);
+        match &self.arch[..] {
+            "x86_64" | "i386" | "i486" | "i586" | "i686" => {
+                let module = if self.arch == "x86_64" { "x86_64" } else { "x86" };
+                let suffix = match (float, bits) {
+                    (true, 32) => "",
+                    (true, 64) => "d",
+                    _ => "i",
+                };
+                match total {
+                    128 | 256 | 512 =>
+                        Ok(format!("::std::arch::{}::__m{}{}", module, total, suffix)),
+                    _ => Err(unsupported()),
+                }
+            }
+            "aarch64" | "arm64" => {
+                let kind = if float { "float" } else if signed { "int" } else { "uint" };
+                match total {
+                    64 | 128 => Ok(format!("::std::arch::aarch64::{}{}x{}_t", kind, bits, count)),
+                    _ => Err(unsupported()),
+                }
+            }
+            _ => Err(unsupported()),
+        }
+    }
+}
+
+/// Whether the attribute is set on the return value (index 0) or a parameter (index 1 onwards)
+unsafe fn has_attribute(function: LLVMValueRef, idx: libc::c_uint, attribute: &str) -> bool {
+    let kind = LLVMGetEnumAttributeKindForName(attribute.as_ptr() as *const libc::c_char,
+                                               attribute.len() as libc::size_t);
+    !LLVMGetEnumAttributeAtIndex(function, idx, kind).is_null()
+}
+
+unsafe fn type_name(ty: LLVMTypeRef) -> String {
+    take_message(LLVMPrintTypeToString(ty))
+}
@@ -0,0 +1,231 @@
+//! Sets of target features
+use std::fmt;
+use std::str::FromStr;
+
+use {BuildError, Triple};
+
+/// Features implied by enabling a feature on x86, as defined by LLVM
+const X86_IMPLIED: &[(&str, &[&str])] = &[
+    ("sse2", &["sse"]),
+    ("sse3", &["sse2"]),
+    ("ssse3", &["sse3"]),
+    ("sse4.1", &["ssse3"]),
+    ("sse4.2", &["sse4.1"]),
+    ("sse4a", &["sse3"]),
+    ("avx", &["sse4.2"]),
+    ("avx2", &["avx"]),
+    ("fma", &["avx"]),
+    ("f16c", &["avx"]),
+    ("avx512f", &["avx2", "fma", "f16c"]),
+    ("avx512bw", &["avx512f"]),
+    ("avx512cd", &["avx512f"]),
+    ("avx512dq", &["avx512f"]),
+    ("avx512vl", &["avx512f"]),
+    ("avx512vbmi", &["avx512bw"]),
+    ("aes", &["sse2"]),
+    ("pclmul", &["sse2"]),
+    ("sha", &["sse2"]),
+    ("gfni", &["sse2"]),
+    ("vaes", &["aes", "avx"]),
+    ("vpclmulqdq", &["avx", "pclmul"]),
+    ("xsaveopt", &["xsave"]),
+    ("xsavec", &["xsave"]),
+    ("xsaves", &["xsave"]),
+];
+
+/// Features enabled by the x86-64 micro-architecture levels
+const X86_CPUS: &[(&str, &[&str])] = &[
+    ("x86-64", &["64bit", "cmov", "cx8", "fxsr", "mmx", "nopl", "sse2", "x87"]),
+    ("x86-64-v2", &["64bit", "cmov", "cx8", "fxsr", "mmx", "nopl", "sse2", "x87",
+                    "cx16", "popcnt", "sahf", "sse4.2"]),
+    ("x86-64-v3", &["64bit", "cmov", "cx8", "fxsr", "mmx", "nopl", "sse2", "x87",
+                    "cx16", "popcnt", "sahf", "sse4.2",
+                    "avx2", "bmi", "bmi2", "f16c", "fma", "lzcnt", "movbe", "xsave"]),
+    ("x86-64-v4", &["64bit", "cmov", "cx8", "fxsr", "mmx", "nopl", "sse2", "x87",
+                    "cx16", "popcnt", "sahf", "sse4.2",
+                    "avx2", "bmi", "bmi2", "f16c", "fma", "lzcnt", "movbe", "xsave",
+                    "avx512bw", "avx512cd", "avx512dq", "avx512vl"]),
+];
+
+/// Features implied by enabling a feature on AArch64, as defined by LLVM
+const AARCH64_IMPLIED: &[(&str, &[&str])] = &[
+    ("neon", &["fp-armv8"]),
+    ("fullfp16", &["fp-armv8"]),
+    ("sve", &["fullfp16"]),
+    ("sve2", &["sve"]),
+    ("aes", &["neon"]),
+    ("sha2", &["neon"]),
+];
+
+/// A set of target features, rendered in the format of `BuildOptions::attr`
+///
+/// ```rust,no_run
+/// extern crate llvm_build_utils;
+/// use llvm_build_utils::*;
+///
+/// fn main() {
+///     let mut features = TargetFeatures::new();
+///     features.enable("avx2").disable("fma");
+///     assert_eq!(features.to_string(), "+avx2,-fma");
+///     Build::new().file("a.ll").attr(features).compile("a");
+/// }
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct TargetFeatures {
+    features: Vec<(String, bool)>,
+}
+
+impl TargetFeatures {
+    /// An empty set of features, leaving every feature at the default of the CPU
+    pub fn new() -> TargetFeatures {
+        TargetFeatures::default()
+    }
+
+    /// Enable a feature, e.g. `avx2`
+    pub fn enable<S: Into<String>>(&mut self, feature: S) -> &mut TargetFeatures {
+        self.set(feature.into(), true)
+    }
+
+    /// Disable a feature, e.g. `avx2`
+    pub fn disable<S: Into<String>>(&mut self, feature: S) -> &mut TargetFeatures {
+        self.set(feature.into(), false)
+    }
+
+    fn set(&mut self, feature: String, enabled: bool) -> &mut TargetFeatures {
+        // Enabling a feature which has been disabled (or vice versa) is kept to report a conflict
+        if !self.features.iter().any(|&(ref f, e)| *f == feature && e == enabled) {
+            self.features.push((feature, enabled));
+        }
+        self
+    }
+
+    /// Whether the feature is enabled (`Some(true)`), disabled (`Some(false)`) or left at the
+    /// default of the CPU (`None`)
+    pub fn get(&self, feature: &str) -> Option<bool> {
+        self.features.iter().rev().find(|&(f, _)| f == feature).map(|&(_, e)| e)
+    }
+
+    /// Iterate over the features and whether they are enabled, in the order they were set
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=(&'a str, bool)> + 'a {
+        self.features.iter().map(|&(ref f, e)| (&f[..], e))
+    }
+
+    /// Features which are both enabled and disabled
+    pub fn conflicts(&self) -> Vec<&str> {
+        let mut ret = self.features.iter()
+            .filter(|&&(ref f, e)| e && self.features.iter().any(|&(ref g, d)| f == g && !d))
+            .map(|(f, _)| &f[..])
+            .collect::<Vec<_>>();
+        ret.dedup();
+        ret
+    }
+
+    /// Check that no feature is both enabled and disabled
+    pub fn check(&self) -> Result<(), BuildError> {
+        match self.conflicts().first() {
+            Some(f) => Err(self.invalid(f, "is both enabled and disabled")),
+            None => Ok(()),
+        }
+    }
+
+    /// The features in effect on the CPU of the target with these features applied
+    ///
+    /// The result contains the features enabled by the CPU, the features enabled by this set and
+    /// every feature they imply, e.g. `+avx2` pulls in `+avx` and everything down to `+sse`.
+    /// Implications and CPU features are known for x86 (CPU features only for the `x86-64`
+    /// micro-architecture levels) and AArch64, on other targets the features are returned as is.
+    ///
+    /// Fails if the set has conflicts, including an enabled feature implying a disabled one.
+    pub fn expand(&self, triple: &str, cpu: &str) -> Result<TargetFeatures, BuildError> {
+        self.check()?;
+        let triple = Triple::parse(triple)?;
+        let (implied, cpus) = match triple.arch() {
+            "x86_64" | "i386" | "i486" | "i586" | "i686" => (X86_IMPLIED, X86_CPUS),
+            "aarch64" | "aarch64_be" | "arm64" => (AARCH64_IMPLIED, &[][..]),
+            _ => (&[][..], &[][..]),
+        };
+        let mut ret = TargetFeatures::new();
+        let by_cpu = cpus.iter().find(|&&(c, _)| c == cpu).map(|&(_, f)| f).unwrap_or(&[]);
+        let mut pending = by_cpu.iter().map(|f| (String::from(*f), true)).collect::<Vec<_>>();
+        pending.extend(self.features.iter().filter(|&&(_, e)| e)
+                                         .map(|(f, _)| (f.clone(), false)));
+        while let Some((feature, cpu_default)) = pending.pop() {
+            match self.get(&feature) {
+                // Features of the CPU may be disabled, along with everything they imply
+                Some(false) if cpu_default => continue,
+                Some(false) => return Err(self.invalid(&feature, "is disabled, but implied by \
+                                                                  an enabled feature")),
+                _ => {}
+            }
+            if ret.get(&feature).is_none() {
+                if let Some(&(_, more)) = implied.iter().find(|&&(f, _)| f == feature) {
+                    pending.extend(more.iter().map(|f| (String::from(*f), cpu_default)));
+                }
+                ret.enable(feature);
+            }
+        }
+        for &(ref feature, enabled) in &self.features {
+            if !enabled {
+                ret.disable(feature.clone());
+            }
+        }
+        ret.features.sort();
+        Ok(ret)
+    }
+
+    fn invalid(&self, feature: &str, reason: &'static str) -> BuildError {
+        BuildError::InvalidFeatures {
+            input: None,
+            features: self.to_string(),
+            feature: String::from(feature),
+            reason
+        }
+    }
+}
+
+impl fmt::Display for TargetFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, &(ref feature, enabled)) in self.features.iter().enumerate() {
+            write!(f, "{}{}{}", if idx == 0 { "" } else { "," },
+                   if enabled { '+' } else { '-' }, feature)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TargetFeatures {
+    type Err = BuildError;
+    /// Parse the format of `BuildOptions::attr`, e.g. `+sse,+sse2,-avx`
+    fn from_str(s: &str) -> Result<TargetFeatures, BuildError> {
+        let mut ret = TargetFeatures::new();
+        for feature in s.split(',').map(|f| f.trim()).filter(|f| !f.is_empty()) {
+            let name = feature.trim_start_matches(['+', '-']);
+            if name.len() + 1 != feature.len() {
+                return Err(BuildError::InvalidFeatures {
+                    input: None,
+                    features: String::from(s),
+                    feature: String::from(feature),
+                    reason: "does not start with a single `+` or `-`"
+                });
+            }
+            if feature.starts_with('+') {
+                ret.enable(name);
+            } else {
+                ret.disable(name);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl From<TargetFeatures> for String {
+    fn from(features: TargetFeatures) -> String {
+        features.to_string()
+    }
+}
+
+impl<'a> From<&'a TargetFeatures> for String {
+    fn from(features: &'a TargetFeatures) -> String {
+        features.to_string()
+    }
+}
@@ -0,0 +1,507 @@
+//! Bindings to the parts of LLVM used by this crate
+use libc;
+
+use {CodegenModel, Optimisation, Relocations};
+
+pub type LLVMBool = libc::c_uint;
+pub const LLVMTrue: LLVMBool = 1;
+pub const LLVMFalse: LLVMBool = 0;
+#[allow(missing_copy_implementations)]
+pub enum LLVMContext_opaque {}
+pub type LLVMContextRef = *mut LLVMContext_opaque;
+#[allow(missing_copy_implementations)]
+pub enum LLVMMemoryBuffer_opaque {}
+pub type LLVMMemoryBufferRef = *mut LLVMMemoryBuffer_opaque;
+#[allow(missing_copy_implementations)]
+pub enum LLVMModule_opaque {}
+pub type LLVMModuleRef = *mut LLVMModule_opaque;
+#[allow(missing_copy_implementations)]
+pub enum LLVMTarget_opaque {}
+pub type LLVMTargetRef = *mut LLVMTarget_opaque;
+#[allow(missing_copy_implementations)]
+pub enum LLVMValue_opaque {}
+pub type LLVMValueRef = *mut LLVMValue_opaque;
+pub enum LLVMType_opaque {}
+pub type LLVMTypeRef = *mut LLVMType_opaque;
+pub enum LLVMAttribute_opaque {}
+pub type LLVMAttributeRef = *mut LLVMAttribute_opaque;
+pub enum LLVMComdat_opaque {}
+pub type LLVMComdatRef = *mut LLVMComdat_opaque;
+pub enum LLVMBasicBlock_opaque {}
+pub type LLVMBasicBlockRef = *mut LLVMBasicBlock_opaque;
+pub enum LLVMMetadata_opaque {}
+pub type LLVMMetadataRef = *mut LLVMMetadata_opaque;
+pub enum LLVMDIBuilder_opaque {}
+pub type LLVMDIBuilderRef = *mut LLVMDIBuilder_opaque;
+pub enum LLVMPassBuilderOptions_opaque {}
+pub type LLVMPassBuilderOptionsRef = *mut LLVMPassBuilderOptions_opaque;
+pub enum LLVMOpaqueError {}
+pub type LLVMErrorRef = *mut LLVMOpaqueError;
+pub enum LLVMDiagnosticInfo_opaque {}
+pub type LLVMDiagnosticInfoRef = *mut LLVMDiagnosticInfo_opaque;
+pub type LLVMDiagnosticHandler = Option<extern "C" fn(LLVMDiagnosticInfoRef, *mut libc::c_void)>;
+pub enum LLVMTargetMachine_opaque {}
+pub type LLVMTargetMachineRef = *mut LLVMTargetMachine_opaque;
+pub enum LLVMBinary_opaque {}
+pub type LLVMBinaryRef = *mut LLVMBinary_opaque;
+pub enum LLVMSectionIterator_opaque {}
+pub type LLVMSectionIteratorRef = *mut LLVMSectionIterator_opaque;
+pub enum LLVMSymbolIterator_opaque {}
+pub type LLVMSymbolIteratorRef = *mut LLVMSymbolIterator_opaque;
+pub enum LLVMRelocationIterator_opaque {}
+pub type LLVMRelocationIteratorRef = *mut LLVMRelocationIterator_opaque;
+pub type LLVMDisasmContextRef = *mut libc::c_void;
+#[cfg(not(feature = "system-llvm"))]
+pub enum LLVMArchiveChild_opaque {}
+#[cfg(not(feature = "system-llvm"))]
+pub type LLVMArchiveChildRef = *mut LLVMArchiveChild_opaque;
+#[cfg(not(feature = "system-llvm"))]
+pub enum LLVMRustArchive_opaque {}
+#[cfg(not(feature = "system-llvm"))]
+pub type LLVMRustArchiveRef = *mut LLVMRustArchive_opaque;
+#[cfg(not(feature = "system-llvm"))]
+pub enum LLVMRustArchiveIterator_opaque {}
+#[cfg(not(feature = "system-llvm"))]
+pub type LLVMRustArchiveIteratorRef = *mut LLVMRustArchiveIterator_opaque;
+#[allow(missing_copy_implementations)]
+#[cfg(not(feature = "system-llvm"))]
+pub enum LLVMRustArchiveMember_opaque {}
+#[cfg(not(feature = "system-llvm"))]
+pub type LLVMRustArchiveMemberRef = *mut LLVMRustArchiveMember_opaque;
+
+extern "C" {
+    pub fn LLVMContextCreate() -> LLVMContextRef;
+    pub fn LLVMContextDispose(C: LLVMContextRef);
+    pub fn LLVMParseIRInContext(context: LLVMContextRef,
+                            buf: LLVMMemoryBufferRef,
+                            om: *mut LLVMModuleRef,
+                            msg: *mut *mut libc::c_char) -> LLVMBool;
+    pub fn LLVMDisposeMemoryBuffer(MemBuf: LLVMMemoryBufferRef);
+    pub fn LLVMSetTarget(M: LLVMModuleRef, Triple: *const libc::c_char);
+    pub fn LLVMDisposeModule(M: LLVMModuleRef);
+    pub fn LLVMCloneModule(M: LLVMModuleRef) -> LLVMModuleRef;
+    pub fn LLVMVerifyModule(_: LLVMModuleRef, _: VerifierFailureAction, _: *mut *mut libc::c_char)
+    -> LLVMBool;
+    pub fn LLVMDisposeMessage(_: *mut libc::c_char);
+    pub fn LLVMVerifyFunction(_: LLVMValueRef, _: VerifierFailureAction) -> LLVMBool;
+    pub fn LLVMGetFirstFunction(M: LLVMModuleRef) -> LLVMValueRef;
+    pub fn LLVMGetNextFunction(Fn: LLVMValueRef) -> LLVMValueRef;
+    pub fn LLVMGetFirstGlobal(M: LLVMModuleRef) -> LLVMValueRef;
+    pub fn LLVMGetNextGlobal(GlobalVar: LLVMValueRef) -> LLVMValueRef;
+    pub fn LLVMGetFirstGlobalAlias(M: LLVMModuleRef) -> LLVMValueRef;
+    pub fn LLVMGetNextGlobalAlias(GA: LLVMValueRef) -> LLVMValueRef;
+    pub fn LLVMIsDeclaration(Global: LLVMValueRef) -> LLVMBool;
+    pub fn LLVMIsAGlobalAlias(Val: LLVMValueRef) -> LLVMValueRef;
+    pub fn LLVMSetSourceFileName(M: LLVMModuleRef, Name: *const libc::c_char, Len: libc::size_t);
+    pub fn LLVMGetTarget(M: LLVMModuleRef) -> *const libc::c_char;
+    pub fn LLVMGetLinkage(Global: LLVMValueRef) -> libc::c_uint;
+    pub fn LLVMSetLinkage(Global: LLVMValueRef, Linkage: libc::c_uint);
+    pub fn LLVMSetVisibility(Global: LLVMValueRef, Viz: libc::c_uint);
+    pub fn LLVMSetComdat(V: LLVMValueRef, C: LLVMComdatRef);
+    pub fn LLVMGetFunctionCallConv(Fn: LLVMValueRef) -> libc::c_uint;
+    pub fn LLVMGlobalGetValueType(Global: LLVMValueRef) -> LLVMTypeRef;
+    pub fn LLVMGetParam(Fn: LLVMValueRef, Index: libc::c_uint) -> LLVMValueRef;
+    pub fn LLVMGetEnumAttributeKindForName(Name: *const libc::c_char, SLen: libc::size_t)
+    -> libc::c_uint;
+    pub fn LLVMGetEnumAttributeAtIndex(F: LLVMValueRef, Idx: libc::c_uint, KindID: libc::c_uint)
+    -> LLVMAttributeRef;
+    pub fn LLVMPrintTypeToString(Ty: LLVMTypeRef) -> *mut libc::c_char;
+    pub fn LLVMGetTypeKind(Ty: LLVMTypeRef) -> libc::c_uint;
+    pub fn LLVMGetIntTypeWidth(IntegerTy: LLVMTypeRef) -> libc::c_uint;
+    pub fn LLVMGetReturnType(FunctionTy: LLVMTypeRef) -> LLVMTypeRef;
+    pub fn LLVMIsFunctionVarArg(FunctionTy: LLVMTypeRef) -> LLVMBool;
+    pub fn LLVMCountParamTypes(FunctionTy: LLVMTypeRef) -> libc::c_uint;
+    pub fn LLVMGetParamTypes(FunctionTy: LLVMTypeRef, Dest: *mut LLVMTypeRef);
+    pub fn LLVMGetElementType(Ty: LLVMTypeRef) -> LLVMTypeRef;
+    pub fn LLVMGetVectorSize(VectorTy: LLVMTypeRef) -> libc::c_uint;
+    pub fn LLVMIsLiteralStruct(StructTy: LLVMTypeRef) -> LLVMBool;
+    pub fn LLVMIsPackedStruct(StructTy: LLVMTypeRef) -> LLVMBool;
+    pub fn LLVMCountStructElementTypes(StructTy: LLVMTypeRef) -> libc::c_uint;
+    pub fn LLVMGetStructElementTypes(StructTy: LLVMTypeRef, Dest: *mut LLVMTypeRef);
+    pub fn LLVMGetValueName2(Val: LLVMValueRef, Length: *mut libc::size_t) -> *const libc::c_char;
+    pub fn LLVMGetNamedFunction(M: LLVMModuleRef, Name: *const libc::c_char) -> LLVMValueRef;
+    pub fn LLVMGetModuleContext(M: LLVMModuleRef) -> LLVMContextRef;
+    pub fn LLVMGetFirstBasicBlock(Fn: LLVMValueRef) -> LLVMBasicBlockRef;
+    pub fn LLVMGetNextBasicBlock(BB: LLVMBasicBlockRef) -> LLVMBasicBlockRef;
+    pub fn LLVMGetFirstInstruction(BB: LLVMBasicBlockRef) -> LLVMValueRef;
+    pub fn LLVMGetNextInstruction(Inst: LLVMValueRef) -> LLVMValueRef;
+    pub fn LLVMInt32TypeInContext(C: LLVMContextRef) -> LLVMTypeRef;
+    pub fn LLVMConstInt(IntTy: LLVMTypeRef, N: libc::c_ulonglong, SignExtend: LLVMBool)
+    -> LLVMValueRef;
+    pub fn LLVMValueAsMetadata(Val: LLVMValueRef) -> LLVMMetadataRef;
+    pub fn LLVMAddModuleFlag(M: LLVMModuleRef, Behavior: libc::c_uint, Key: *const libc::c_char,
+                             KeyLen: libc::size_t, Val: LLVMMetadataRef);
+    pub fn LLVMDebugMetadataVersion() -> libc::c_uint;
+    pub fn LLVMStripModuleDebugInfo(M: LLVMModuleRef) -> LLVMBool;
+    pub fn LLVMCreateDIBuilder(M: LLVMModuleRef) -> LLVMDIBuilderRef;
+    pub fn LLVMDIBuilderFinalize(Builder: LLVMDIBuilderRef);
+    pub fn LLVMDisposeDIBuilder(Builder: LLVMDIBuilderRef);
+    pub fn LLVMDIBuilderCreateFile(Builder: LLVMDIBuilderRef,
+                                   Filename: *const libc::c_char, FilenameLen: libc::size_t,
+                                   Directory: *const libc::c_char, DirectoryLen: libc::size_t)
+    -> LLVMMetadataRef;
+    pub fn LLVMDIBuilderCreateCompileUnit(Builder: LLVMDIBuilderRef, Lang: libc::c_uint,
+                                          FileRef: LLVMMetadataRef,
+                                          Producer: *const libc::c_char,
+                                          ProducerLen: libc::size_t,
+                                          isOptimized: LLVMBool,
+                                          Flags: *const libc::c_char, FlagsLen: libc::size_t,
+                                          RuntimeVer: libc::c_uint,
+                                          SplitName: *const libc::c_char,
+                                          SplitNameLen: libc::size_t,
+                                          Kind: libc::c_uint, DWOId: libc::c_uint,
+                                          SplitDebugInlining: LLVMBool,
+                                          DebugInfoForProfiling: LLVMBool,
+                                          SysRoot: *const libc::c_char, SysRootLen: libc::size_t,
+                                          SDK: *const libc::c_char, SDKLen: libc::size_t)
+    -> LLVMMetadataRef;
+    pub fn LLVMDIBuilderCreateSubroutineType(Builder: LLVMDIBuilderRef, File: LLVMMetadataRef,
+                                             ParameterTypes: *mut LLVMMetadataRef,
+                                             NumParameterTypes: libc::c_uint,
+                                             Flags: libc::c_int)
+    -> LLVMMetadataRef;
+    pub fn LLVMDIBuilderCreateFunction(Builder: LLVMDIBuilderRef, Scope: LLVMMetadataRef,
+                                       Name: *const libc::c_char, NameLen: libc::size_t,
+                                       LinkageName: *const libc::c_char,
+                                       LinkageNameLen: libc::size_t,
+                                       File: LLVMMetadataRef, LineNo: libc::c_uint,
+                                       Ty: LLVMMetadataRef, IsLocalToUnit: LLVMBool,
+                                       IsDefinition: LLVMBool, ScopeLine: libc::c_uint,
+                                       Flags: libc::c_int, IsOptimized: LLVMBool)
+    -> LLVMMetadataRef;
+    pub fn LLVMSetSubprogram(Func: LLVMValueRef, SP: LLVMMetadataRef);
+    pub fn LLVMDIBuilderCreateDebugLocation(Ctx: LLVMContextRef, Line: libc::c_uint,
+                                            Column: libc::c_uint, Scope: LLVMMetadataRef,
+                                            InlinedAt: LLVMMetadataRef)
+    -> LLVMMetadataRef;
+    pub fn LLVMInstructionSetDebugLoc(Inst: LLVMValueRef, Loc: LLVMMetadataRef);
+    pub fn LLVMSetValueName2(Val: LLVMValueRef, Name: *const libc::c_char, NameLen: libc::size_t);
+    pub fn LLVMGetBufferStart(MemBuf: LLVMMemoryBufferRef) -> *const libc::c_char;
+    pub fn LLVMGetBufferSize(MemBuf: LLVMMemoryBufferRef) -> libc::size_t;
+    pub fn LLVMCreateTargetMachine(tr: LLVMTargetRef,
+                               triple: *const libc::c_char,
+                               cpu: *const libc::c_char,
+                               features: *const libc::c_char,
+                               lvl: Optimisation,
+                               reloc: Relocations,
+                               cm: CodegenModel) -> LLVMTargetMachineRef;
+    pub fn LLVMDisposeTargetMachine(_: LLVMTargetMachineRef);
+    pub fn LLVMTargetMachineEmitToFile (_: LLVMTargetMachineRef,
+                                            _: LLVMModuleRef,
+                                            filename: *const libc::c_char,
+                                            _: CodeGenFileType,
+                                            err: *mut *mut libc::c_char) -> LLVMBool;
+    pub fn LLVMNormalizeTargetTriple(triple: *const libc::c_char) -> *mut libc::c_char;
+    pub fn LLVMGetFirstTarget() -> LLVMTargetRef;
+    pub fn LLVMGetNextTarget(T: LLVMTargetRef) -> LLVMTargetRef;
+    pub fn LLVMGetTargetName(T: LLVMTargetRef) -> *const libc::c_char;
+    pub fn LLVMTargetMachineEmitToMemoryBuffer(_: LLVMTargetMachineRef,
+                                               _: LLVMModuleRef,
+                                               _: CodeGenFileType,
+                                               err: *mut *mut libc::c_char,
+                                               out: *mut LLVMMemoryBufferRef) -> LLVMBool;
+    pub fn LLVMCreatePassBuilderOptions() -> LLVMPassBuilderOptionsRef;
+    pub fn LLVMDisposePassBuilderOptions(Options: LLVMPassBuilderOptionsRef);
+    pub fn LLVMRunPasses(M: LLVMModuleRef,
+                         Passes: *const libc::c_char,
+                         TM: LLVMTargetMachineRef,
+                         Options: LLVMPassBuilderOptionsRef) -> LLVMErrorRef;
+    pub fn LLVMGetErrorMessage(Err: LLVMErrorRef) -> *mut libc::c_char;
+    pub fn LLVMDisposeErrorMessage(ErrMsg: *mut libc::c_char);
+    pub fn LLVMContextSetDiagnosticHandler(C: LLVMContextRef,
+                                           Handler: LLVMDiagnosticHandler,
+                                           DiagnosticContext: *mut libc::c_void);
+    pub fn LLVMGetDiagInfoDescription(DI: LLVMDiagnosticInfoRef) -> *mut libc::c_char;
+    pub fn LLVMGetDiagInfoSeverity(DI: LLVMDiagnosticInfoRef) -> LLVMDiagnosticSeverity;
+    pub fn LLVMLinkModules2(Dest: LLVMModuleRef, Src: LLVMModuleRef) -> LLVMBool;
+    pub fn LLVMGetTargetFromTriple(triple: *const libc::c_char,
+                               _: *mut LLVMTargetRef,
+                               err: *mut *mut libc::c_char) -> LLVMBool;
+    pub fn LLVMCreateBinary(MemBuf: LLVMMemoryBufferRef, Context: LLVMContextRef,
+                            ErrorMessage: *mut *mut libc::c_char) -> LLVMBinaryRef;
+    pub fn LLVMDisposeBinary(BR: LLVMBinaryRef);
+    pub fn LLVMObjectFileCopySectionIterator(BR: LLVMBinaryRef) -> LLVMSectionIteratorRef;
+    pub fn LLVMObjectFileIsSectionIteratorAtEnd(BR: LLVMBinaryRef, SI: LLVMSectionIteratorRef)
+    -> LLVMBool;
+    pub fn LLVMObjectFileCopySymbolIterator(BR: LLVMBinaryRef) -> LLVMSymbolIteratorRef;
+    pub fn LLVMObjectFileIsSymbolIteratorAtEnd(BR: LLVMBinaryRef, SI: LLVMSymbolIteratorRef)
+    -> LLVMBool;
+    pub fn LLVMDisposeSectionIterator(SI: LLVMSectionIteratorRef);
+    pub fn LLVMMoveToNextSection(SI: LLVMSectionIteratorRef);
+    pub fn LLVMDisposeSymbolIterator(SI: LLVMSymbolIteratorRef);
+    pub fn LLVMMoveToNextSymbol(SI: LLVMSymbolIteratorRef);
+    pub fn LLVMGetSectionName(SI: LLVMSectionIteratorRef) -> *const libc::c_char;
+    pub fn LLVMGetSectionSize(SI: LLVMSectionIteratorRef) -> u64;
+    pub fn LLVMGetSectionContents(SI: LLVMSectionIteratorRef) -> *const libc::c_char;
+    pub fn LLVMGetSectionAddress(SI: LLVMSectionIteratorRef) -> u64;
+    pub fn LLVMGetSectionContainsSymbol(SI: LLVMSectionIteratorRef, Sym: LLVMSymbolIteratorRef)
+    -> LLVMBool;
+    pub fn LLVMGetRelocations(Section: LLVMSectionIteratorRef) -> LLVMRelocationIteratorRef;
+    pub fn LLVMDisposeRelocationIterator(RI: LLVMRelocationIteratorRef);
+    pub fn LLVMIsRelocationIteratorAtEnd(Section: LLVMSectionIteratorRef,
+                                         RI: LLVMRelocationIteratorRef) -> LLVMBool;
+    pub fn LLVMMoveToNextRelocation(RI: LLVMRelocationIteratorRef);
+    pub fn LLVMGetRelocationOffset(RI: LLVMRelocationIteratorRef) -> u64;
+    pub fn LLVMGetRelocationSymbol(RI: LLVMRelocationIteratorRef) -> LLVMSymbolIteratorRef;
+    pub fn LLVMGetSymbolName(SI: LLVMSymbolIteratorRef) -> *const libc::c_char;
+    pub fn LLVMGetSymbolAddress(SI: LLVMSymbolIteratorRef) -> u64;
+    pub fn LLVMCreateDisasmCPUFeatures(Triple: *const libc::c_char, CPU: *const libc::c_char,
+                                       Features: *const libc::c_char, DisInfo: *mut libc::c_void,
+                                       TagType: libc::c_int, GetOpInfo: *const libc::c_void,
+                                       SymbolLookUp: *const libc::c_void)
+    -> LLVMDisasmContextRef;
+    pub fn LLVMSetDisasmOptions(DC: LLVMDisasmContextRef, Options: u64) -> libc::c_int;
+    pub fn LLVMDisasmDispose(DC: LLVMDisasmContextRef);
+    pub fn LLVMDisasmInstruction(DC: LLVMDisasmContextRef, Bytes: *mut u8, BytesSize: u64,
+                                 PC: u64, OutString: *mut libc::c_char,
+                                 OutStringSize: libc::size_t) -> libc::size_t;
+
+    pub fn LLVMCreateMemoryBufferWithMemoryRangeCopy(InputData: *const libc::c_char,
+                                                     InputDataLength: libc::size_t,
+                                                     BufferName: *const libc::c_char)
+                                                     -> LLVMMemoryBufferRef;
+}
+
+// Unstable Rust’s LLVM bindings, which a system LLVM does not have
+#[cfg(not(feature = "system-llvm"))]
+extern "C" {
+    pub fn LLVMRustGetLastError() -> *const libc::c_char;
+    pub fn LLVMRustOpenArchive(path: *const libc::c_char) -> LLVMRustArchiveRef;
+    pub fn LLVMRustDestroyArchive(RAR: LLVMRustArchiveRef);
+    pub fn LLVMRustArchiveIteratorNew(RAR: LLVMRustArchiveRef) -> LLVMRustArchiveIteratorRef;
+    pub fn LLVMRustArchiveIteratorNext(RAI: LLVMRustArchiveIteratorRef) -> LLVMArchiveChildRef;
+    pub fn LLVMRustArchiveIteratorFree(RAI: LLVMRustArchiveIteratorRef);
+    pub fn LLVMRustArchiveChildName(ACR: LLVMArchiveChildRef, size: *mut libc::size_t)
+    -> *const libc::c_char;
+    pub fn LLVMRustArchiveChildFree(ACR: LLVMArchiveChildRef);
+    pub fn LLVMRustArchiveMemberNew(_: *const libc::c_char,
+                                _: *const libc::c_char,
+                                _: LLVMArchiveChildRef) -> LLVMRustArchiveMemberRef;
+    pub fn LLVMRustArchiveMemberFree(_: LLVMRustArchiveMemberRef);
+    pub fn LLVMRustWriteArchive(Dst: *const libc::c_char,
+                                NumMembers: libc::size_t,
+                                Members: *const LLVMRustArchiveMemberRef,
+                                WriteSymbtab: bool,
+                                Kind: LLVMRustArchiveKind,
+                                isEC: bool) -> libc::c_int;
+}
+
+/// Archive formats, numbered as by `LLVMRustArchiveKind` of rustc
+#[cfg(not(feature = "system-llvm"))]
+#[allow(dead_code)]
+#[repr(C)]
+pub enum LLVMRustArchiveKind {
+    Gnu = 0,
+    Bsd = 1,
+    Darwin = 2,
+    Coff = 3,
+    AixBig = 4,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+pub enum VerifierFailureAction {
+    AbortProcess = 0,
+    PrintMessage = 1,
+    ReturnStatus = 2,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(PartialEq)]
+pub enum LLVMDiagnosticSeverity {
+    Error = 0,
+    Warning = 1,
+    Remark = 2,
+    Note = 3,
+}
+
+pub const LLVMInternalLinkage: libc::c_uint = 8;
+pub const LLVMPrivateLinkage: libc::c_uint = 9;
+
+pub const LLVMDefaultVisibility: libc::c_uint = 0;
+
+pub const LLVMVoidTypeKind: libc::c_uint = 0;
+pub const LLVMFloatTypeKind: libc::c_uint = 2;
+pub const LLVMDoubleTypeKind: libc::c_uint = 3;
+pub const LLVMIntegerTypeKind: libc::c_uint = 8;
+pub const LLVMStructTypeKind: libc::c_uint = 10;
+pub const LLVMPointerTypeKind: libc::c_uint = 12;
+pub const LLVMVectorTypeKind: libc::c_uint = 13;
+
+pub const LLVMAttributeReturnIndex: libc::c_uint = 0;
+
+pub const LLVMDWARFSourceLanguageC: libc::c_uint = 1;
+pub const LLVMDWARFEmissionLineTablesOnly: libc::c_uint = 2;
+pub const LLVMModuleFlagBehaviorWarning: libc::c_uint = 1;
+
+pub const LLVMDisassembler_Option_AsmPrinterVariant: u64 = 4;
+
+#[allow(dead_code)]
+#[repr(C)]
+pub enum CodeGenFileType {
+    Assembly = 0,
+    Object = 1,
+}
+
+/// Owned LLVM context, disposed on drop
+pub struct Context(pub LLVMContextRef);
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe { LLVMContextDispose(self.0) }
+    }
+}
+
+/// Owned LLVM module, disposed on drop
+///
+/// Must be dropped before the context it belongs to.
+pub struct Module(pub LLVMModuleRef);
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeModule(self.0) }
+    }
+}
+
+/// Owned LLVM target machine, disposed on drop
+pub struct TargetMachine(pub LLVMTargetMachineRef);
+
+impl Drop for TargetMachine {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeTargetMachine(self.0) }
+    }
+}
+
+/// Owned LLVM memory buffer, disposed on drop
+pub struct MemoryBuffer(pub LLVMMemoryBufferRef);
+
+impl MemoryBuffer {
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            ::std::slice::from_raw_parts(LLVMGetBufferStart(self.0) as *const u8,
+                                         LLVMGetBufferSize(self.0) as usize)
+        }
+    }
+}
+
+impl Drop for MemoryBuffer {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeMemoryBuffer(self.0) }
+    }
+}
+
+/// Owned object file opened for reading, disposed on drop
+///
+/// Must be dropped before the memory buffer it was opened from.
+pub struct Binary(pub LLVMBinaryRef);
+
+impl Drop for Binary {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeBinary(self.0) }
+    }
+}
+
+/// Owned disassembler, disposed on drop
+pub struct Disassembler(pub LLVMDisasmContextRef);
+
+impl Drop for Disassembler {
+    fn drop(&mut self) {
+        unsafe { LLVMDisasmDispose(self.0) }
+    }
+}
+
+/// Owned debug information builder, disposed on drop
+pub struct DIBuilder(pub LLVMDIBuilderRef);
+
+impl Drop for DIBuilder {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeDIBuilder(self.0) }
+    }
+}
+
+/// Owned archive opened for reading, destroyed on drop
+#[cfg(not(feature = "system-llvm"))]
+pub struct Archive(pub LLVMRustArchiveRef);
+
+#[cfg(not(feature = "system-llvm"))]
+impl Drop for Archive {
+    fn drop(&mut self) {
+        unsafe { LLVMRustDestroyArchive(self.0) }
+    }
+}
+
+/// Owned member of an archive, freed on drop
+///
+/// Must be dropped before the archive it belongs to.
+#[cfg(not(feature = "system-llvm"))]
+pub struct ArchiveChild(pub LLVMArchiveChildRef);
+
+#[cfg(not(feature = "system-llvm"))]
+impl Drop for ArchiveChild {
+    fn drop(&mut self) {
+        unsafe { LLVMRustArchiveChildFree(self.0) }
+    }
+}
+
+/// Take the last error reported by Rust’s LLVM bindings, if any
+#[cfg(not(feature = "system-llvm"))]
+pub unsafe fn last_error() -> Option<String> {
+    let err = LLVMRustGetLastError();
+    if err.is_null() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(::std::ffi::CStr::from_ptr(err).to_bytes()).into_owned())
+    }
+}
+
+/// Consume the error and return its message
+pub unsafe fn take_error(err: LLVMErrorRef) -> String {
+    let msg = LLVMGetErrorMessage(err);
+    let ret = String::from_utf8_lossy(::std::ffi::CStr::from_ptr(msg).to_bytes()).into_owned();
+    LLVMDisposeErrorMessage(msg);
+    ret
+}
+
+/// Get the name of a value
+pub unsafe fn value_name(val: LLVMValueRef) -> String {
+    let mut len = 0;
+    let ptr = LLVMGetValueName2(val, &mut len);
+    if ptr.is_null() {
+        return String::new();
+    }
+    let bytes = ::std::slice::from_raw_parts(ptr as *const u8, len);
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Iterate over all the functions in a module
+pub unsafe fn functions(module: LLVMModuleRef) -> Values {
+    Values { next: LLVMGetFirstFunction(module), step: LLVMGetNextFunction }
+}
+
+/// Iterate over all the global variables in a module
+pub unsafe fn global_variables(module: LLVMModuleRef) -> Values {
+    Values { next: LLVMGetFirstGlobal(module), step: LLVMGetNextGlobal }
+}
+
+/// Iterate over all the aliases in a module
+pub unsafe fn global_aliases(module: LLVMModuleRef) -> Values {
+    Values { next: LLVMGetFirstGlobalAlias(module), step: LLVMGetNextGlobalAlias }
+}
+
+pub struct Values {
+    next: LLVMValueRef,
+    step: unsafe extern "C" fn(LLVMValueRef) -> LLVMValueRef,
+}
+
+impl Iterator for Values {
+    type Item = LLVMValueRef;
+    fn next(&mut self) -> Option<LLVMValueRef> {
+        if self.next.is_null() {
+            return None;
+        }
+        let ret = self.next;
+        self.next = unsafe { (self.step)(ret) };
+        Some(ret)
+    }
+}
@@ -0,0 +1,87 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// LLVM-IR or LLVM bytecode to build
+///
+/// Paths and strings convert into `Input::File`. IR generated by the build script itself may be
+/// built without writing it to disk first by using `Input::memory` or `Input::read`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Input {
+    /// A file on disk
+    File(PathBuf),
+    /// IR held in memory
+    Memory {
+        /// Name of the input, used in diagnostics and to name the archive member
+        name: String,
+        /// The textual IR or bytecode
+        contents: Vec<u8>,
+    },
+}
+
+impl Input {
+    /// IR held in memory
+    ///
+    /// `name` is used in diagnostics and to name the archive member, e.g. `tables.ll`.
+    pub fn memory<N: Into<String>, C: Into<Vec<u8>>>(name: N, contents: C) -> Input {
+        Input::Memory { name: name.into(), contents: contents.into() }
+    }
+
+    /// IR read out of a reader into memory
+    ///
+    /// `name` is used in diagnostics and to name the archive member, e.g. `tables.ll`.
+    pub fn read<N: Into<String>, R: Read>(name: N, mut reader: R) -> io::Result<Input> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        Ok(Input::memory(name, contents))
+    }
+
+    /// Name of the input used in diagnostics: the path for files, the given name otherwise
+    pub fn name(&self) -> &Path {
+        match *self {
+            Input::File(ref p) => p,
+            Input::Memory { ref name, .. } => Path::new(name),
+        }
+    }
+
+    /// Path to the input, if it is a file
+    pub fn path(&self) -> Option<&Path> {
+        match *self {
+            Input::File(ref p) => Some(p),
+            Input::Memory { .. } => None,
+        }
+    }
+
+    /// Get the contents of the input
+    pub fn contents<'a>(&'a self) -> io::Result<Cow<'a, [u8]>> {
+        match *self {
+            Input::File(ref p) => fs::read(p).map(Cow::Owned),
+            Input::Memory { ref contents, .. } => Ok(Cow::Borrowed(contents)),
+        }
+    }
+}
+
+impl<'a> From<&'a Path> for Input {
+    fn from(p: &'a Path) -> Input {
+        Input::File(p.to_path_buf())
+    }
+}
+
+impl From<PathBuf> for Input {
+    fn from(p: PathBuf) -> Input {
+        Input::File(p)
+    }
+}
+
+impl<'a> From<&'a str> for Input {
+    fn from(p: &'a str) -> Input {
+        Input::File(PathBuf::from(p))
+    }
+}
+
+impl From<String> for Input {
+    fn from(p: String) -> Input {
+        Input::File(PathBuf::from(p))
+    }
+}
@@ -0,0 +1,61 @@
+//! Running independent units of work concurrently
+use std::cmp;
+use std::env;
+use std::panic;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use jobserver::Client;
+
+/// Number of jobs cargo allows the build script to run
+///
+/// Reads `$NUM_JOBS` and defaults to 1 when it is not set.
+pub fn default_jobs() -> usize {
+    env::var("NUM_JOBS").ok().and_then(|v| v.parse().ok()).unwrap_or(1)
+}
+
+/// The jobserver cargo has passed to the build script, if any
+fn jobserver() -> Option<&'static Client> {
+    static CLIENT: OnceLock<Option<Client>> = OnceLock::new();
+    CLIENT.get_or_init(|| unsafe { Client::from_env() }).as_ref()
+}
+
+/// Call `f` for every index in `0..count` on up to `jobs` threads
+///
+/// The first thread relies on the job token implicitly held by this process, every other thread
+/// acquires a token from cargo’s jobserver (if there is one) for each unit of work. Results are
+/// returned in index order, regardless of the order in which the work completes.
+pub fn run<T, F>(count: usize, jobs: usize, f: F) -> Vec<T>
+where T: Send + 'static, F: Fn(usize) -> T + Send + Sync + 'static {
+    let threads = cmp::max(1, cmp::min(jobs, count));
+    if threads == 1 {
+        return (0..count).map(f).collect();
+    }
+    let f = Arc::new(f);
+    let next = Arc::new(AtomicUsize::new(0));
+    let results = Arc::new(Mutex::new((0..count).map(|_| None).collect::<Vec<Option<T>>>()));
+    let handles = (0..threads).map(|worker| {
+        let (f, next, results) = (f.clone(), next.clone(), results.clone());
+        thread::spawn(move || loop {
+            let idx = next.fetch_add(1, Ordering::SeqCst);
+            if idx >= count {
+                break;
+            }
+            let _token = if worker == 0 {
+                None
+            } else {
+                jobserver().and_then(|c| c.acquire().ok())
+            };
+            let result = f(idx);
+            results.lock().expect("a worker panicked")[idx] = Some(result);
+        })
+    }).collect::<Vec<_>>();
+    for handle in handles {
+        if let Err(e) = handle.join() {
+            panic::resume_unwind(e);
+        }
+    }
+    let mut results = results.lock().expect("a worker panicked");
+    results.drain(..).map(|r| r.expect("all of the work is done")).collect()
+}
@@ -40,103 +40,89 @@
 //!
 //! Running a `cargo build` should produce `libyourthing.a` which then may be linked to your Rust
 //! executable/library.
+//!
+//! Alternatively, the [`Build`](struct.Build.html) builder applies the same options to all of
+//! the inputs and names the archive following the conventions of the target:
+//!
+//! ```rust,no_run
+//! extern crate llvm_build_utils;
+//! use llvm_build_utils::*;
+//!
+//! fn main() {
+//!     Build::new().file("input.ll").cpu("x86-64").compile("yourthing");
+//! }
+//! ```
+//!
+//! # System LLVM
+//!
+//! Should the LLVM of rustc be unusable, the `system-llvm` feature links to the shared LLVM
+//! library of an installed LLVM instead. It is found through the `llvm-config` named by
+//! `$LLVM_CONFIG_PATH`, the one in `$LLVM_SYS_<version>_PREFIX/bin` or the one in `$PATH`, in
+//! this order. The library must be loadable when the build script runs. Only the stable C API of
+//! LLVM is used then, archives are read and written by this crate itself.
+//!
+//! Alternatively, `Build::backend` generates the code with another [`Backend`](trait.Backend.html),
+//! such as `ExternalTools`, which drives the `opt`, `llc` and `llvm-ar` executables of an LLVM
+//! installation.
 #![allow(non_camel_case_types, non_upper_case_globals)]
+extern crate jobserver;
 extern crate libc;
 extern crate mktemp;
 extern crate target_build_utils;
 
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 use std::ffi::{CString, CStr, OsString, OsStr};
-use std::sync::{Once, ONCE_INIT};
-
-type LLVMBool = libc::c_uint;
-const LLVMTrue: LLVMBool = 1;
-const LLVMFalse: LLVMBool = 0;
-#[allow(missing_copy_implementations)]
-enum LLVMContext_opaque {}
-type LLVMContextRef = *mut LLVMContext_opaque;
-#[allow(missing_copy_implementations)]
-enum LLVMMemoryBuffer_opaque {}
-type LLVMMemoryBufferRef = *mut LLVMMemoryBuffer_opaque;
-#[allow(missing_copy_implementations)]
-enum LLVMModule_opaque {}
-type LLVMModuleRef = *mut LLVMModule_opaque;
-#[allow(missing_copy_implementations)]
-enum LLVMTarget_opaque {}
-type LLVMTargetRef = *mut LLVMTarget_opaque;
-enum LLVMTargetMachine_opaque {}
-type LLVMTargetMachineRef = *mut LLVMTargetMachine_opaque;
-enum LLVMArchiveChild_opaque {}
-type LLVMArchiveChildRef = *mut LLVMArchiveChild_opaque;
-#[allow(missing_copy_implementations)]
-enum LLVMRustArchiveMember_opaque {}
-type LLVMRustArchiveMemberRef = *mut LLVMRustArchiveMember_opaque;
-
-extern {
-    fn LLVMContextCreate() -> LLVMContextRef;
-    fn LLVMContextDispose(C: LLVMContextRef);
-    fn LLVMParseIRInContext(context: LLVMContextRef,
-                            buf: LLVMMemoryBufferRef,
-                            om: *mut LLVMModuleRef,
-                            msg: *mut *mut libc::c_char) -> LLVMBool;
-    // fn LLVMDisposeMemoryBuffer(MemBuf: LLVMMemoryBufferRef);
-    fn LLVMSetTarget(M: LLVMModuleRef, Triple: *const libc::c_char);
-    fn LLVMDisposeModule(M: LLVMModuleRef);
-    fn LLVMVerifyModule(_: LLVMModuleRef, _: VerifierFailureAction, _: *mut *mut libc::c_char)
-    -> LLVMBool;
-    fn LLVMDisposeMessage(_: *mut libc::c_char);
-    fn LLVMCreateTargetMachine(tr: LLVMTargetRef,
-                               triple: *const libc::c_char,
-                               cpu: *const libc::c_char,
-                               features: *const libc::c_char,
-                               lvl: Optimisation,
-                               reloc: Relocations,
-                               cm: CodegenModel) -> LLVMTargetMachineRef;
-    fn LLVMDisposeTargetMachine(_: LLVMTargetMachineRef);
-    fn LLVMTargetMachineEmitToFile (_: LLVMTargetMachineRef,
-                                            _: LLVMModuleRef,
-                                            filename: *const libc::c_char,
-                                            _: CodeGenFileType,
-                                            err: *mut *mut libc::c_char) -> LLVMBool;
-    fn LLVMGetTargetFromTriple(triple: *const libc::c_char,
-                               _: *mut LLVMTargetRef,
-                               err: *mut *mut libc::c_char) -> LLVMBool;
-
-    // Unstable Rust’s LLVM bindings
-    fn LLVMRustCreateMemoryBufferWithContentsOfFile(Path: *const libc::c_char)
-                                                    -> LLVMMemoryBufferRef;
-    fn LLVMRustGetLastError() -> *const libc::c_char;
-    fn LLVMRustArchiveMemberNew(_: *const libc::c_char,
-                                _: *const libc::c_char,
-                                _: LLVMArchiveChildRef) -> LLVMRustArchiveMemberRef;
-    fn LLVMRustArchiveMemberFree(_: LLVMRustArchiveMemberRef);
-    fn LLVMRustWriteArchive(Dst: *const libc::c_char,
-                            NumMembers: libc::size_t,
-                            Members: *const LLVMRustArchiveMemberRef,
-                            WriteSymbtab: bool,
-                            Kind: ArchiveKind) -> libc::c_int;
-}
-
-#[allow(dead_code)]
-#[repr(C)]
-enum VerifierFailureAction {
-    AbortProcess = 0,
-    PrintMessage = 1,
-    ReturnStatus = 2,
-}
+use std::fs::{self, File};
+use std::io::Write;
+use std::sync::Arc;
 
-#[allow(dead_code)]
-#[repr(C)]
-enum CodeGenFileType {
-    Assembly = 0,
-    Object = 1,
-}
+pub use archive::{archive_members, ExistingArchive};
+pub use backend::{ArchiveMember, Backend, Emitted, InProcess, Parsed};
+pub use builder::Build;
+
+use cache::Cache;
+pub use diagnostic::Diagnostic;
+pub use error::BuildError;
+pub use features::TargetFeatures;
+pub use input::Input;
+pub use matrix::{TargetArchive, TargetArchives};
+pub use subtarget::{target_cpus, target_features, TargetEntry};
+pub use triple::Triple;
+
+use ffi::*;
+use targets::initialize_llvm;
+pub use targets::{available_backends, available_targets};
+pub use tools::ExternalTools;
+
+#[cfg(feature = "system-llvm")]
+mod ar;
+mod archive;
+mod backend;
+mod builder;
+mod cache;
+mod debuginfo;
+mod diagnostic;
+mod disasm;
+mod error;
+mod externs;
+mod features;
+mod ffi;
+mod input;
+mod jobs;
+mod matrix;
+mod multiversion;
+mod subtarget;
+mod symbols;
+mod targets;
+mod tools;
+mod triple;
 
 
 /// Relocation mode
 ///
 /// This option decides how relocations are handled.
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 #[repr(C)]
 pub enum Relocations {
     /// Target default relocation model
@@ -151,7 +137,7 @@ pub enum Relocations {
 
 /// Codegen model
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum CodegenModel {
     /// Target default code model
     Default = 0,
@@ -166,7 +152,7 @@ pub enum CodegenModel {
 }
 
 /// Codegen optimisation level
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 #[repr(C)]
 pub enum Optimisation {
     /// No codegen optimisation
@@ -187,13 +173,71 @@ pub enum Optimisation {
     O3 = 3,
 }
 
+/// IR optimisation pipeline
+///
+/// Unlike `Optimisation`, which only affects code generation, this decides which of the
+/// target-independent optimisations (inlining, instcombine, GVN, vectorisation, etc.) run on the
+/// module before code generation.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Passes {
+    /// No IR optimisations
+    None,
+    /// The standard pipeline for the level chosen in `BuildOptions::opt`
+    ///
+    /// Corresponds to the `-passes=default<On>` option of `opt`.
+    Standard,
+    /// A custom pipeline, such as `function(instcombine,gvn),globaldce`
+    ///
+    /// Corresponds to the `-passes` option of `opt`.
+    Custom(String),
+}
+
+/// Syntax of textual assembly listings
+///
+/// Only x86 targets support a choice of syntax, the setting is ignored for other targets.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AssemblySyntax {
+    /// AT&T syntax, the default for x86 targets
+    Att,
+    /// Intel syntax
+    ///
+    /// LLVM only generates Intel syntax assembly when told to through a process-wide option, so
+    /// `InProcess` disassembles these listings from the object file instead. They have the labels
+    /// of the symbols and the instructions, but none of the directives.
+    Intel,
+}
+
+/// Debug information in the generated code
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DebugInfo {
+    /// Strip any debug information present in the inputs
+    Strip,
+    /// Keep the debug information present in the inputs as is
+    Preserve,
+    /// Generate line tables mapping the machine code to the lines of the textual IR
+    ///
+    /// Debug information present in the inputs is replaced. Line tables refer to the inputs by
+    /// their names, relative to the current directory (relative to `.` in deterministic mode).
+    /// Bytecode inputs keep their debug information as is.
+    ///
+    /// Corresponds to the `-debugify` option of `opt`, with the lines of the actual input.
+    LineTables,
+}
+
 /// The format of generated archive file
+///
+/// Member names of any length can be stored in every format: names which do not fit the 16 bytes
+/// of the member header always go through the `//` table of GNU and COFF archives or follow the
+/// header as `#1/<length>` in BSD archives.
 #[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum ArchiveKind {
     /// GNU archive format, default in majority of cases
     Gnu,
     /// MIPS64 archive format
+    ///
+    /// The `InProcess` backend writes GNU archives instead, which LLVM gives the 64-bit symbol
+    /// table of this format once the archive is too large for a 32-bit one.
     Mips64,
     /// BSD archive format, default on OS X and iOS systems
     Bsd,
@@ -201,6 +245,19 @@ pub enum ArchiveKind {
     Coff,
 }
 
+impl ArchiveKind {
+    /// Gets the ArchiveKind conventionally used by the target triple
+    pub fn for_target(triple: &str) -> ArchiveKind {
+        if triple.contains("-apple-") || triple.contains("darwin") || triple.contains("-ios") {
+            ArchiveKind::Bsd
+        } else if triple.contains("msvc") {
+            ArchiveKind::Coff
+        } else {
+            ArchiveKind::Gnu
+        }
+    }
+}
+
 impl Default for ArchiveKind {
     /// Gets the default ArchiveKind depending on `TARGET` variable
     ///
@@ -216,7 +273,7 @@ impl Default for ArchiveKind {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct BuildOptions {
     /// Target triple to generate machine code for
     ///
@@ -228,12 +285,18 @@ pub struct BuildOptions {
     /// * `<sys>`    none, linux, win32, darwin, cuda, etc.
     /// * `<abi>`    eabi, gnu, android, macho, elf, etc.
     ///
-    /// *Defaults* to `$TARGET` environment variable, if set (always is in cargo build scripts).
+    /// Use `Triple` to parse the triple or to inspect its components. The triple is validated
+    /// before any input is built.
+    ///
+    /// *Defaults* to `$TARGET` environment variable converted to the triple rustc uses (see
+    /// `Triple::from_rust_target`), if set (always is in cargo build scripts).
     ///
     /// Corresponds to the `-mtriple` option of `llc`.
     pub triple: String,
     /// Target CPU to generate machine code for
     ///
+    /// `target_cpus` lists the CPUs known to a target.
+    ///
     /// *Default* is chosen depending on the target `triple`.
     ///
     /// Corresponds to the `-mcpu` option of `llc`.
@@ -243,7 +306,8 @@ pub struct BuildOptions {
     /// Format of this field is the same as the format for `-mattr` option: +feature enables a
     /// feature, -feature disables it. Each feature is delimited by a comma.
     ///
-    /// Sample string: `+sse,+sse2,+sse3,-avx`.
+    /// Sample string: `+sse,+sse2,+sse3,-avx`. `TargetFeatures` builds such strings and
+    /// `target_features` lists the features known to a target.
     ///
     /// *Default* is chosen depending on the target `triple`.
     ///
@@ -268,15 +332,68 @@ pub struct BuildOptions {
     ///
     /// Corresponds to the `-O` option of `llc`.
     pub opt: Optimisation,
-    /// Name of the archive section to insert generated object into
+    /// IR optimisation pipeline run before code generation
+    ///
+    /// *Defaults* to `Passes::Standard`, that is, the level specified in the `$OPT_LEVEL`
+    /// environment variable unless `opt` is changed.
+    pub passes: Passes,
+    /// Name of the archive member to insert generated object into
+    ///
+    /// Must be unique within the archive and at most 255 bytes long.
+    ///
+    /// *Defaults* to an empty string, which names the member after the input and the
+    /// architecture, e.g. `test.x86_64.o`. Clashes between such names are resolved with a numeric
+    /// suffix, e.g. `test.x86_64.1.o`.
     pub ar_section_name: String,
+    /// Also write a textual assembly listing in the given syntax
+    ///
+    /// The listing is written to `$OUT_DIR/<input stem>.<arch>.s`.
+    ///
+    /// *Defaults* to `None`.
+    ///
+    /// Corresponds to the `-filetype=asm` and `-x86-asm-syntax` options of `llc`.
+    pub asm_listing: Option<AssemblySyntax>,
+    /// Prefix prepended to the names of the externally visible definitions
+    ///
+    /// References to the definitions from within the same input (or the inputs linked with it)
+    /// follow along. References to symbols defined elsewhere, including other members of the
+    /// archive, are left alone; rename those with `symbol_renames` instead.
+    ///
+    /// *Defaults* to an empty string, which keeps the names as they are.
+    pub symbol_prefix: String,
+    /// Symbols to rename, from the original name to the new one
+    ///
+    /// Unlike `symbol_prefix`, applies to the references to symbols defined elsewhere as well.
+    /// Takes precedence over `symbol_prefix`. The names of the renamed definitions are reported
+    /// by `Printout::renamed_symbols`.
+    ///
+    /// *Defaults* to an empty map.
+    pub symbol_renames: BTreeMap<String, String>,
+    /// Symbols to keep externally visible, by their names after renaming
+    ///
+    /// Every other definition is internalised before the IR optimisations run, so that the unused
+    /// ones are removed. Building fails if a symbol in the set is not defined by any input of the
+    /// archive.
+    ///
+    /// *Defaults* to `None`, which keeps all definitions visible.
+    ///
+    /// Corresponds to the `-internalize-public-api-list` option of `opt`.
+    pub exports: Option<BTreeSet<String>>,
+    /// Debug information to generate
+    ///
+    /// *Defaults* to `DebugInfo::Preserve` if the `$DEBUG` environment variable (set by cargo) is
+    /// `true` and `DebugInfo::Strip` otherwise.
+    pub debug: DebugInfo,
 }
 
 impl Default for BuildOptions {
     fn default() -> BuildOptions {
         use std::env::var;
         BuildOptions {
-            triple: var("TARGET").unwrap_or(String::new()),
+            triple: var("TARGET").map(|t| match Triple::from_rust_target(&t) {
+                Ok(triple) => triple.to_string(),
+                Err(_) => t,
+            }).unwrap_or_default(),
             cpu: String::new(),
             attr: String::new(),
             model: CodegenModel::Default,
@@ -285,9 +402,18 @@ impl Default for BuildOptions {
                 0 => Optimisation::O0,
                 1 => Optimisation::O1,
                 2 => Optimisation::O2,
-                3 | _ => Optimisation::O3,
+                _ => Optimisation::O3,
             },
+            passes: Passes::Standard,
             ar_section_name: String::new(),
+            asm_listing: None,
+            symbol_prefix: String::new(),
+            symbol_renames: BTreeMap::new(),
+            exports: None,
+            debug: match var("DEBUG").as_ref().map(|d| &d[..]) {
+                Ok("true") => DebugInfo::Preserve,
+                _ => DebugInfo::Strip,
+            },
         }
     }
 }
@@ -296,7 +422,13 @@ impl Default for BuildOptions {
 pub struct Printout {
     libname: String,
     outdir: OsString,
-    deps: Vec<String>
+    deps: Vec<String>,
+    listings: Vec<PathBuf>,
+    cache_hits: usize,
+    externs: Option<PathBuf>,
+    renamed: Vec<(String, String)>,
+    dispatcher: Option<PathBuf>,
+    warnings: Vec<String>,
 }
 
 impl Printout {
@@ -306,18 +438,55 @@ impl Printout {
     ///
     /// * What library to link to (`print_link`);
     /// * Where to look for the library in question (`print_path`);
-    /// * List of dependencies which trigger the rebuild (`print_deps`).
+    /// * List of dependencies which trigger the rebuild (`print_deps`);
+    /// * Warnings about the build (`print_warnings`).
     ///
     /// All of these may also be printed separately via other methods on this struct.
     pub fn print(mut self) {
         self.print_link();
         self.print_path();
         self.print_deps();
+        self.print_warnings();
+    }
+
+    /// Paths to the assembly listings written alongside the archive
+    pub fn asm_listings(&self) -> &[PathBuf] {
+        &self.listings
+    }
+
+    /// Number of archive members reused from previous builds rather than compiled anew
+    pub fn cache_hits(&self) -> usize {
+        self.cache_hits
+    }
+
+    /// Path to the Rust declarations of the functions defined by the archive, if requested
+    pub fn extern_decls(&self) -> Option<&Path> {
+        self.externs.as_deref()
+    }
+
+    /// Definitions renamed by `BuildOptions::symbol_prefix` or `BuildOptions::symbol_renames`, as
+    /// `(original, renamed)` pairs
+    ///
+    /// The pairs follow the order of the archive members and are sorted by the original name
+    /// within a member.
+    pub fn renamed_symbols(&self) -> &[(String, String)] {
+        &self.renamed
+    }
+
+    /// Path to the dispatchers of the multiversioned inputs, if any (see `Build::multiversion`)
+    pub fn dispatcher(&self) -> Option<&Path> {
+        self.dispatcher.as_deref()
+    }
+
+    /// Warnings about the build, such as the CPUs or features which could not be checked or the
+    /// functions line tables could not be generated for
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
     }
 
     /// Inform cargo of the library to link to
     pub fn print_link(&mut self) {
-        let name = ::std::mem::replace(&mut self.libname, String::new());
+        let name = ::std::mem::take(&mut self.libname);
         if !name.is_empty() {
             println!("cargo:rustc-link-lib={}", name);
         }
@@ -336,67 +505,106 @@ impl Printout {
 
     /// Inform cargo of the dependencies which should trigger a rebuild
     pub fn print_deps(&mut self) {
-        let deps = ::std::mem::replace(&mut self.deps, Vec::new());
+        let deps = ::std::mem::take(&mut self.deps);
         for dep in deps {
             println!("cargo:rerun-if-changed={}", dep);
         }
     }
+
+    /// Have cargo show the warnings about the build
+    pub fn print_warnings(&mut self) {
+        let warnings = ::std::mem::take(&mut self.warnings);
+        for warning in warnings {
+            println!("cargo:warning={}", warning);
+        }
+    }
 }
 
 
 
-fn initialize_llvm() {
-    static ONCE: Once = ONCE_INIT;
-
-    macro_rules! init_target(
-        ($($method:ident),*) => { {
-            extern { $(fn $method();)* }
-            $($method();)*
-        } }
-    );
-
-    ONCE.call_once(|| unsafe {
-        init_target!(LLVMInitializeX86TargetInfo,
-                     LLVMInitializeX86Target,
-                     LLVMInitializeX86TargetMC,
-                     LLVMInitializeX86AsmPrinter,
-                     LLVMInitializeX86AsmParser,
-                     LLVMInitializeARMTargetInfo,
-                     LLVMInitializeARMTarget,
-                     LLVMInitializeARMTargetMC,
-                     LLVMInitializeARMAsmPrinter,
-                     LLVMInitializeARMAsmParser,
-                     LLVMInitializeAArch64TargetInfo,
-                     LLVMInitializeAArch64Target,
-                     LLVMInitializeAArch64TargetMC,
-                     LLVMInitializeAArch64AsmPrinter,
-                     LLVMInitializeAArch64AsmParser,
-                     LLVMInitializeMipsTargetInfo,
-                     LLVMInitializeMipsTarget,
-                     LLVMInitializeMipsTargetMC,
-                     LLVMInitializeMipsAsmPrinter,
-                     LLVMInitializeMipsAsmParser,
-                     LLVMInitializePowerPCTargetInfo,
-                     LLVMInitializePowerPCTarget,
-                     LLVMInitializePowerPCTargetMC,
-                     LLVMInitializePowerPCAsmPrinter,
-                     LLVMInitializePowerPCAsmParser);
-    });
+macro_rules! fail_if {
+    ($ex: expr, $err: expr) => {
+        if $ex { return Err($err) }
+    }
 }
 
-macro_rules! fail_if {
-    ($ex: expr, $($args: tt)*) => {
-        if $ex { return Err(format!($($args)*)) }
+/// Copy the message produced by LLVM and dispose the original
+unsafe fn take_message(msg: *mut libc::c_char) -> String {
+    if msg.is_null() {
+        return String::new();
+    }
+    let ret = String::from_utf8_lossy(CStr::from_ptr(msg).to_bytes()).into_owned();
+    LLVMDisposeMessage(msg);
+    ret
+}
+
+/// Attribute an error about the triple or the features to the input built with them
+fn with_input(e: BuildError, input: &Input) -> BuildError {
+    match e {
+        BuildError::InvalidTriple { triple, reason, .. } => BuildError::InvalidTriple {
+            input: Some(input.name().to_path_buf()),
+            triple,
+            reason
+        },
+        BuildError::InvalidFeatures { features, feature, reason, .. } =>
+            BuildError::InvalidFeatures {
+                input: Some(input.name().to_path_buf()),
+                features,
+                feature,
+                reason
+            },
+        e => e,
+    }
+}
+
+/// Check that the CPU and the features requested by `opt` are known to the target
+///
+/// When the CPUs and features of the target cannot be listed, the check is skipped with a warning.
+fn check_subtarget(input: &Input, opt: &BuildOptions, warnings: &mut Vec<String>)
+-> Result<(), BuildError> {
+    let features = opt.attr.parse::<TargetFeatures>().map_err(|e| with_input(e, input))?;
+    features.check().map_err(|e| with_input(e, input))?;
+    if opt.triple.is_empty() || (opt.cpu.is_empty() && opt.attr.is_empty()) {
+        return Ok(());
+    }
+    // A target which is not available is reported as an error once the target machine is
+    // created, this only warns of the skipped check
+    let known = match subtarget::query(&opt.triple) {
+        Ok(known) => known,
+        Err(e) => {
+            let warning = format!("did not check the requested CPU and features: {}", e);
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+            return Ok(());
+        }
+    };
+    let unrecognised = |field, name: &str, entries: &[TargetEntry]| BuildError::Unrecognised {
+        input: input.name().to_path_buf(),
+        options: Box::new(opt.clone()),
+        field,
+        name: String::from(name),
+        suggestion: subtarget::closest(name, entries).map(String::from),
+    };
+    if !opt.cpu.is_empty() && opt.cpu != "generic" && !known.cpus.is_empty() &&
+       !known.cpus.iter().any(|c| c.name == opt.cpu) {
+        return Err(unrecognised("cpu", &opt.cpu, &known.cpus));
+    }
+    for (name, _) in features.iter() {
+        if !known.features.is_empty() && !known.features.iter().any(|f| f.name == name) {
+            return Err(unrecognised("attr", name, &known.features));
+        }
     }
+    Ok(())
 }
 
 /// Produce a static library (archive) containing machine code
 ///
 /// The input files must be well formed LLVM-IR files or LLVM bytecode. Format of the input file
 /// is autodetected.
-pub fn build_archive<'a, P: 'a, I>(archive: P, iter: I)
--> Result<Printout, String>
-where P: AsRef<Path>, I: IntoIterator<Item=&'a (P, BuildOptions)> {
+pub fn build_archive<'a, P, I>(archive: P, iter: I)
+-> Result<Printout, BuildError>
+where P: AsRef<Path> + 'a, I: IntoIterator<Item=&'a (P, BuildOptions)> {
     build_archive_kind(ArchiveKind::default(), archive, iter)
 }
 
@@ -404,132 +612,778 @@ where P: AsRef<Path>, I: IntoIterator<Item=&'a (P, BuildOptions)> {
 ///
 /// The input files must be well formed LLVM-IR files or LLVM bytecode. Format of the input file
 /// is autodetected.
-pub fn build_archive_kind<'a, P: 'a, I>(format: ArchiveKind, archive: P, iter: I)
--> Result<Printout, String>
-where P: AsRef<Path>, I: IntoIterator<Item=&'a (P, BuildOptions)>
+pub fn build_archive_kind<'a, P, I>(format: ArchiveKind, archive: P, iter: I)
+-> Result<Printout, BuildError>
+where P: AsRef<Path> + 'a, I: IntoIterator<Item=&'a (P, BuildOptions)>
 {
+    let libstem = {
+        let archive_name = |reason| BuildError::ArchiveName {
+            archive: archive.as_ref().to_path_buf(),
+            reason
+        };
+        fail_if!(archive.as_ref().extension() != Some(OsStr::new("a")),
+                 archive_name("extension must be .a"));
+        let libstem = archive.as_ref().file_stem().and_then(|s| s.to_str()).ok_or_else(||
+                      archive_name("output filename has invalid stem"))?;
+        fail_if!(!libstem.starts_with("lib"), archive_name("output filename must start with lib"));
+        String::from(&libstem[3..])
+    };
+    let outpath = PathBuf::from(::std::env::var_os("OUT_DIR").unwrap_or_default());
+    let out_target = outpath.join(archive);
+    let (inputs, options): (Vec<_>, Vec<_>) = iter.into_iter().map(|(p, opt)| {
+        (Input::from(p.as_ref()), opt)
+    }).unzip();
+    let inputs = inputs.iter().zip(options).collect::<Vec<_>>();
+    write_archive(format, &out_target, libstem, &inputs, &Settings::default())
+}
+
+/// Settings which apply to the archive as a whole, rather than to individual inputs
+#[derive(Clone, Debug)]
+struct Settings {
+    /// Maximum number of inputs compiled concurrently
+    jobs: usize,
+    /// Whether to reuse objects compiled by previous builds
+    cache: bool,
+    /// Whether to link the inputs sharing the same options into a single object
+    link: bool,
+    /// Whether to write the Rust declarations of the functions defined by the archive
+    externs: bool,
+    /// Existing archives, the members of which are put into the archive
+    archives: Vec<PathBuf>,
+    /// Whether members replace earlier members of the same name
+    replace: bool,
+    /// Whether the archive must be reproducible bit for bit
+    deterministic: bool,
+    /// The code generator the archive is built with
+    backend: Arc<dyn Backend>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            jobs: jobs::default_jobs(),
+            cache: true,
+            link: false,
+            externs: false,
+            archives: Vec::new(),
+            replace: false,
+            deterministic: false,
+            backend: Arc::new(InProcess),
+        }
+    }
+}
+
+/// Compile the inputs and write them into the archive at `out_target`
+fn write_archive(format: ArchiveKind, out_target: &Path, libname: String,
+                 inputs: &[(&Input, &BuildOptions)], settings: &Settings)
+-> Result<Printout, BuildError> {
     initialize_llvm();
-    let mut strings = vec![];
-    let mut members = vec![];
+    let mut names = vec![];
+    let mut objects = vec![];
     let mut temps = vec![];
     let mut deps = vec![];
-    let outpath = ::std::env::var_os("OUT_DIR").unwrap_or_default();
-    let libstem = {
-        fail_if!(archive.as_ref().extension() != Some(OsStr::new("a")), "extension must be .a");
-        let libstem = try!(archive.as_ref().file_stem().and_then(|s| s.to_str()).ok_or_else(||
-                           String::from("output filename has invalid stem")));
-        fail_if!(!libstem.starts_with("lib"), "output filename must start with lib");
-        String::from(&libstem[3..])
+    let mut listings = vec![];
+    let mut externs = String::new();
+    let mut renamed = vec![];
+    let mut exported = vec![];
+    let mut cache_hits = 0;
+    let outdir = out_target.parent().unwrap_or(Path::new(""));
+
+    for path in inputs.iter().filter_map(|&(input, _)| input.path()) {
+        deps.push(String::from(path.to_str().ok_or_else(|| BuildError::InvalidPath {
+            path: path.to_path_buf(),
+            reason: "input filename is not utf-8"
+        })?));
+    }
+
+    for path in &settings.archives {
+        deps.push(String::from(path.to_str().ok_or_else(|| BuildError::InvalidPath {
+            path: path.to_path_buf(),
+            reason: "archive filename is not utf-8"
+        })?));
+    }
+    let existing = settings.archives.iter().map(|a| ExistingArchive::open(a))
+                           .collect::<Result<Vec<_>, _>>()?;
+
+    for &(input, opt) in inputs.iter().filter(|&&(_, opt)| !opt.triple.is_empty()) {
+        Triple::parse(&opt.triple).map_err(|e| with_input(e, input))?;
+    }
+    let mut warnings = vec![];
+    for &(input, opt) in inputs {
+        check_subtarget(input, opt, &mut warnings)?;
+    }
+
+    // Every unit is compiled in its own context, so that they may be compiled concurrently
+    let units = Arc::new(Unit::group(inputs, settings.link));
+    let member_names = member_names(&units)?;
+    let results = {
+        let units = units.clone();
+        let outdir = outdir.to_path_buf();
+        let cache = if settings.cache {
+            Some(Cache::new(&outdir, settings.backend.version()?))
+        } else {
+            None
+        };
+        let settings = settings.clone();
+        jobs::run(units.len(), settings.jobs, move |idx| {
+            compile_cached(cache.as_ref(), &units[idx], &outdir, &settings)
+        })
     };
 
-    unsafe {
-        let ctx = LLVMContextCreate();
-        fail_if!(ctx.is_null(), "could not create the context");
-        for &(ref p, ref opt) in iter {
-            let mut module = ::std::ptr::null_mut();
-            let mut msg = ::std::ptr::null_mut();
-
-            let input_str = try!(p.as_ref().to_str().ok_or_else(||
-                                 String::from("input filename is not utf-8")));
-            deps.push(String::from(input_str));
-            // Read the LLVM-IR/BC into memory
-            let path = try!(CString::new(input_str).map_err(|_|
-                            String::from("input filename contains nulls")));
-            let buf = LLVMRustCreateMemoryBufferWithContentsOfFile(path.as_ptr());
-            fail_if!(buf.is_null(), "could not open input file {:?}: {:?}",
-                    p.as_ref(), CStr::from_ptr(LLVMRustGetLastError()));
-
-            // Parse the IR/BC
-            LLVMParseIRInContext(ctx, buf, &mut module, &mut msg);
-            fail_if!(module.is_null(), "module could not be parsed successfully: {:?}",
-                     CStr::from_ptr(msg));
-            if LLVMVerifyModule(module, VerifierFailureAction::ReturnStatus, &mut msg) == LLVMTrue {
-                let ret = Err(format!("Module is not valid: {:?}", CStr::from_ptr(msg)));
-                LLVMDisposeMessage(msg);
-                return ret;
+    // The archive writer names members after their files, so the objects are archived from a
+    // directory of their own, under the member names
+    let mut staging: Option<mktemp::Temp> = None;
+
+    for ((unit, compiled), name) in units.iter().zip(results).zip(member_names) {
+        let compiled = compiled?;
+        listings.extend(compiled.listing);
+        renamed.extend(compiled.renamed);
+        exported.extend(compiled.exported);
+        warnings.extend(compiled.warnings);
+        externs.push_str(compiled.externs.as_ref().map(|e| &e[..]).unwrap_or(""));
+        if compiled.cached {
+            cache_hits += 1;
+        }
+
+        // Put the built objects into an archive
+        let input = unit.inputs[0].name();
+        let temp_error = |e| BuildError::TempFile { input: input.to_path_buf(), error: e };
+        if staging.is_none() {
+            staging = Some(mktemp::Temp::new_dir_in(outdir).map_err(&temp_error)?);
+        }
+        let staged = staging.as_ref().expect("created above").as_ref().join(&name);
+        let object = &compiled.object;
+        fs::hard_link(object, &staged)
+            .or_else(|_| fs::copy(object, &staged).map(|_| ()))
+            .map_err(&temp_error)?;
+        objects.push(staged);
+        names.push(CString::new(name).expect("checked by member_names"));
+        temps.extend(compiled.temp);
+    }
+
+    for unit in units.iter() {
+        let mut exports = unit.options.exports.iter().flat_map(|e| e.iter());
+        if let Some(symbol) = exports.find(|s| !exported.contains(s)) {
+            return Err(BuildError::UndefinedExport {
+                input: unit.inputs[0].name().to_path_buf(),
+                symbol: symbol.clone()
+            });
+        }
+    }
+
+    // Members of the existing archives come first, followed by the built objects
+    let old = existing.iter().flat_map(|archive| {
+        archive.members.iter().enumerate().map(move |(idx, (name, _))| {
+            (name.as_bytes(), &name[..], ArchiveMember::Existing(archive, idx))
+        })
+    });
+    let new = objects.iter().zip(names.iter()).map(|(object, name)| {
+        (name.as_bytes(), &name[..], ArchiveMember::Object(object))
+    });
+    let mut ordered: Vec<(&[u8], _, _)> = Vec::new();
+    for member in old.chain(new) {
+        if settings.replace && !member.0.is_empty() {
+            if let Some(replaced) = ordered.iter_mut().find(|m| m.0 == member.0) {
+                *replaced = member;
+                continue;
+            }
+        }
+        ordered.push(member);
+    }
+    let members = ordered.into_iter().map(|(_, name, member)| (name, member))
+                         .collect::<Vec<_>>();
+    settings.backend.write_archive(out_target, format, &members)?;
+
+    let externs = if settings.externs {
+        let path = outdir.join(format!("{}.rs", libname));
+        let contents = format!("// Declarations of the functions defined by {}\n{}",
+                               out_target.display(), externs);
+        fs::write(&path, contents).map_err(|e|
+            BuildError::ExternDecls { path: path.clone(), error: e })?;
+        Some(path)
+    } else {
+        None
+    };
+
+    Ok(Printout {
+        libname,
+        outdir: outdir.as_os_str().to_os_string(),
+        deps,
+        listings,
+        cache_hits,
+        externs,
+        renamed,
+        dispatcher: None,
+        warnings,
+    })
+}
+
+/// Result of compiling a single input
+struct Compiled {
+    /// Path to the object file
+    object: PathBuf,
+    /// The object file is removed once this is dropped, unless the object comes from the cache
+    temp: Option<mktemp::Temp>,
+    /// Path to the assembly listing, if one was requested
+    listing: Option<PathBuf>,
+    /// Rust declarations of the functions defined by the object, if requested
+    externs: Option<String>,
+    /// Definitions renamed in the object, as `(original, renamed)` pairs
+    renamed: Vec<(String, String)>,
+    /// Symbols listed in `BuildOptions::exports` the object defines
+    exported: Vec<String>,
+    /// Problems which did not prevent compiling the object
+    warnings: Vec<String>,
+    /// Whether the object comes from the cache
+    cached: bool,
+}
+
+/// Inputs which are compiled into a single archive member
+struct Unit {
+    /// The inputs, linked together in this order
+    inputs: Vec<Input>,
+    options: BuildOptions,
+}
+
+impl Unit {
+    /// Group the inputs into units
+    ///
+    /// Unless `link` is set, every input is a unit of its own. Otherwise inputs sharing the same
+    /// options form a unit. Units are ordered by their first input.
+    fn group(inputs: &[(&Input, &BuildOptions)], link: bool) -> Vec<Unit> {
+        let mut units: Vec<Unit> = Vec::new();
+        for &(input, opt) in inputs {
+            if link {
+                if let Some(unit) = units.iter_mut().find(|u| u.options == *opt) {
+                    unit.inputs.push(input.clone());
+                    continue;
+                }
             }
+            units.push(Unit { inputs: vec![input.clone()], options: opt.clone() });
+        }
+        units
+    }
+}
+
+/// Compile a unit with the backend, reusing the object in the cache if there is one
+fn compile_cached(cache: Option<&Cache>, unit: &Unit, outdir: &Path, settings: &Settings)
+-> Result<Compiled, BuildError> {
+    let (input, opt) = (unit.inputs[0].name(), &unit.options);
+    // Inputs which cannot be read are not cached, parsing reports the error instead.
+    let key = cache.and_then(|cache| {
+        let contents = unit.inputs.iter().map(|i| i.contents()).collect::<Result<Vec<_>, _>>();
+        contents.ok().map(|c| cache.key(&unit.inputs, &c, opt, settings.deterministic))
+    });
+    if let (Some(cache), Some(key)) = (cache, key.as_ref()) {
+        if let Some(entry) = cache.lookup(key, opt, settings.externs) {
+            let listing = match entry.listing {
+                Some(cached) => {
+                    let path = outdir.join(listing_name(input, opt));
+                    fs::copy(&cached, &path).map_err(|e|
+                        BuildError::Listing { path: path.clone(), error: e })?;
+                    Some(path)
+                }
+                None => None
+            };
+            return Ok(Compiled {
+                object: entry.object,
+                temp: None,
+                listing,
+                externs: entry.externs,
+                renamed: entry.renamed,
+                exported: entry.exported,
+                warnings: entry.warnings,
+                cached: true,
+            });
+        }
+    }
+
+    let parsed = settings.backend.parse(&unit.inputs, opt, settings.deterministic)?;
+    parsed.verify()?;
+    let temp = mktemp::Temp::new_file_in(outdir).map_err(|e|
+               BuildError::TempFile { input: input.to_path_buf(), error: e })?;
+    let object = temp.to_path_buf();
+    let listing = opt.asm_listing.map(|_| outdir.join(listing_name(input, opt)));
+    let emitted = parsed.emit_object(&object, listing.as_deref(),
+                                     settings.externs)?;
+    let mut object = object;
+    if let (Some(cache), Some(key)) = (cache, key.as_ref()) {
+        // Failing to populate the cache is not fatal, the next build will just do the work again.
+        if let Ok(entry) = cache.store(key, &object, listing.as_deref(), &emitted) {
+            object = entry.object;
+        }
+    }
+    Ok(Compiled {
+        object,
+        temp: Some(temp),
+        listing,
+        externs: emitted.externs,
+        renamed: emitted.renamed,
+        exported: emitted.exported,
+        warnings: emitted.warnings,
+        cached: false
+    })
+}
 
-            // Build the IR/BC to object file
-            let triple = CString::new(opt.triple.clone()).expect("triple contains null bytes");
-            let cpu = CString::new(opt.cpu.clone()).expect("cpu contains null bytes");
-            let attr = CString::new(opt.attr.clone()).expect("attr contains null bytes");
-            if !opt.triple.is_empty() {
-                LLVMSetTarget(module, triple.as_ptr());
+/// Names of the archive members built from the units
+///
+/// Members are named after `ar_section_name` if it is set, or otherwise after the first input
+/// and the architecture. Clashes between the latter are resolved with a numeric suffix.
+///
+/// Objects are staged as files named after their member before they are archived, so the names
+/// are limited to the 255 bytes most file systems allow.
+fn member_names(units: &[Unit]) -> Result<Vec<String>, BuildError> {
+    const MAX_NAME: usize = 255;
+    let mut names: Vec<Option<String>> = Vec::new();
+    for unit in units {
+        let name = &unit.options.ar_section_name;
+        if name.is_empty() {
+            names.push(None);
+            continue;
+        }
+        let invalid = |reason| BuildError::MemberName {
+            input: unit.inputs[0].name().to_path_buf(),
+            name: name.clone(),
+            reason
+        };
+        fail_if!(name.contains(['/', '\\', '\n', '\0']),
+                 invalid("member names cannot contain `/`, `\\`, newlines or nulls"));
+        fail_if!(name == "." || name == "..", invalid("member names cannot be `.` or `..`"));
+        fail_if!(name.len() > MAX_NAME, invalid("member names cannot be longer than 255 bytes"));
+        fail_if!(names.iter().any(|n| n.as_ref() == Some(name)),
+                 invalid("another member already has this name"));
+        names.push(Some(name.clone()));
+    }
+    let taken = names.iter().filter_map(|n| n.clone()).collect::<Vec<_>>();
+    let mut ret: Vec<String> = Vec::new();
+    for (name, unit) in names.into_iter().zip(units) {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                let input = unit.inputs[0].name();
+                let stem = input.file_stem().unwrap_or(input.as_os_str()).to_string_lossy()
+                                .replace(['\n', '\0'], "_");
+                let arch = unit.options.triple.split('-').next().unwrap_or("");
+                let base = if arch.is_empty() { stem } else { format!("{}.{}", stem, arch) };
+                let mut name = format!("{}.o", base);
+                let mut n = 1;
+                while taken.contains(&name) || ret.contains(&name) {
+                    name = format!("{}.{}.o", base, n);
+                    n += 1;
+                }
+                fail_if!(name.len() > MAX_NAME, BuildError::MemberName {
+                    input: input.to_path_buf(),
+                    name,
+                    reason: "the name given to the member by default is longer than 255 bytes, \
+                             set `ar_section_name`"
+                });
+                name
             }
-            let mut target = ::std::ptr::null_mut();
-            let status = LLVMGetTargetFromTriple(triple.as_ptr(), &mut target, &mut msg);
-            fail_if!(status != LLVMFalse, "could not generate target from triple {}: {:?}",
-                     opt.triple, CStr::from_ptr(msg));
-            let machine = LLVMCreateTargetMachine(target,
-                                                  triple.as_ptr(),
-                                                  cpu.as_ptr(),
-                                                  attr.as_ptr(),
-                                                  opt.opt,
-                                                  opt.reloc,
-                                                  opt.model);
-            fail_if!(machine.is_null(), "could not create the target machine \
-                                         (likely invalid BuildOptions {:?})", opt);
-
-
-            let tmp = try!(mktemp::Temp::new_file_in(
-                p.as_ref().parent().expect("cannot get basename of input filename")
-            ).map_err(|e| format!("could not create temp file: {}", e)));
-            let object_file = try!(CString::new(
-                try!(tmp.as_ref().to_str().ok_or_else(|| String::from("object path is not utf-8")))
-            ).map_err(|_| String::from("object filename contains nulls")));
-            temps.push(tmp);
-
-            let status = LLVMTargetMachineEmitToFile(machine,
-                                                     module,
-                                                     object_file.as_ptr(),
-                                                     CodeGenFileType::Object,
-                                                     &mut msg);
-            fail_if!(status == LLVMTrue, "could not generate object file: {:?}",
-                    CStr::from_ptr(msg));
-
-            // Put the built objects into an archive
-            let name = try!(CString::new(opt.ar_section_name.clone())
-                            .map_err(|_| String::from("archive member name contains nulls")));
-            members.push(LLVMRustArchiveMemberNew(object_file.as_ptr(),
-                                                  name.as_ptr(),
-                                                  std::ptr::null_mut()));
-            strings.push(name);
-            strings.push(object_file);
-
-            LLVMDisposeTargetMachine(machine);
-            LLVMDisposeModule(module);
-            // FIXME: SIGSEGVS for some reason
-            // LLVMDisposeMemoryBuffer(buf);
+        };
+        ret.push(name);
+    }
+    Ok(ret)
+}
+
+/// Convert a path to a string LLVM accepts
+fn path_to_cstring(path: &Path) -> Result<CString, BuildError> {
+    let invalid_path = |reason| BuildError::InvalidPath {
+        path: path.to_path_buf(),
+        reason
+    };
+    let s = path.to_str().ok_or_else(|| invalid_path("path is not utf-8"))?;
+    CString::new(s).map_err(|_| invalid_path("path contains nulls"))
+}
+
+/// Compile the linked module of a unit into the object file at `object`
+///
+/// `input` is the first input of the unit, reported in errors which do not relate to a specific
+/// input. The assembly listing is written to `listing`, if given.
+unsafe fn compile_module(module: Module, input: &Path, opt: &BuildOptions, deterministic: bool,
+                         object: &Path, listing: Option<&Path>, externs: bool)
+-> Result<Emitted, BuildError> {
+    let renamed = rename_symbols(&module, input, opt)?;
+    let exported = match opt.exports {
+        Some(ref exports) => symbols::internalise(&module, exports),
+        None => Vec::new(),
+    };
+    if deterministic {
+        // The source file name ends up in the object, do not let it depend on the directory
+        let name = input.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        LLVMSetSourceFileName(module.0, name.as_ptr() as *const libc::c_char,
+                              name.len() as libc::size_t);
+    }
+    let machine = target_machine(&module, input, opt)?;
+    let externs = if externs {
+        Some(externs::declarations(&module).map_err(|e| BuildError::UnsupportedSignature {
+            input: input.to_path_buf(),
+            function: e.function,
+            reason: e.reason,
+        })?)
+    } else {
+        None
+    };
+    optimise(&machine, &module, input, opt)?;
+    if let (Some(path), Some(syntax)) = (listing, opt.asm_listing) {
+        // Code generation may modify the module, so generate the listing from a copy
+        let copy = Module(LLVMCloneModule(module.0));
+        let text = assembly_listing(&machine, &copy, input, opt, syntax)?;
+        File::create(path).and_then(|mut f| f.write_all(text.as_bytes())).map_err(|e|
+            BuildError::Listing { path: path.to_path_buf(), error: e })?;
+    }
+    emit_object(&machine, &module, input, opt, object)?;
+    Ok(Emitted {
+        externs,
+        renamed,
+        exported,
+        warnings: Vec::new(),
+    })
+}
+
+/// Parse and verify the inputs and link them into a single module, with the debug information
+/// `opt` asks for
+unsafe fn link_inputs(ctx: LLVMContextRef, inputs: &[Input], opt: &BuildOptions,
+                      deterministic: bool, warnings: &mut Vec<String>)
+-> Result<Module, BuildError> {
+    let mut modules = Vec::new();
+    for input in inputs {
+        let (module, source) = parse_input(ctx, input)?;
+        verify_input(&module, input.name(), source.as_ref().map(|s| &s[..]))?;
+        modules.push((module, source));
+    }
+    let names = inputs.iter().map(|i| i.name().to_path_buf()).collect::<Vec<_>>();
+    link_modules(ctx, modules, &names, opt, deterministic, warnings)
+}
+
+/// Link the verified modules of the inputs into a single module, with the debug information
+/// `opt` asks for
+///
+/// The modules come along with the source of textual IR inputs, `inputs` are the names of the
+/// inputs they were parsed from. Problems with the debug information are reported in `warnings`.
+unsafe fn link_modules(ctx: LLVMContextRef, modules: Vec<(Module, Option<String>)>,
+                       inputs: &[PathBuf], opt: &BuildOptions, deterministic: bool,
+                       warnings: &mut Vec<String>)
+-> Result<Module, BuildError> {
+    let directory = if deterministic {
+        PathBuf::from(".")
+    } else {
+        ::std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+    };
+    let mut linked: Option<Module> = None;
+    for ((other, source), input) in modules.into_iter().zip(inputs) {
+        if let (DebugInfo::LineTables, Some(source)) = (opt.debug, source) {
+            debuginfo::line_tables(&other, &source, input, &directory, warnings);
         }
-        let out_target = Path::new(&outpath).join(archive);
-        let libname_str = try!(out_target.to_str().ok_or_else(||
-                               String::from("archive filename is not utf-8")));
-        let dest = try!(CString::new(libname_str).map_err(|_|
-                        String::from("output file has interior nulls")));
-        let r = LLVMRustWriteArchive(dest.as_ptr(),
-                                     members.len() as libc::size_t,
-                                     members.as_ptr(),
-                                     true,
-                                     format);
-        fail_if!(r != 0, "{:?}", {
-            let err = LLVMRustGetLastError();
-            if err.is_null() {
-                "failed to write archive".to_string()
-            } else {
-                String::from_utf8_lossy(CStr::from_ptr(err).to_bytes())
-                        .into_owned()
+        let module = match linked {
+            Some(ref module) => module,
+            None => {
+                linked = Some(other);
+                continue;
             }
-        });
-        for member in members {
-            LLVMRustArchiveMemberFree(member);
+        };
+        // The linker reports the problems through the diagnostic handler of the context
+        let mut messages: Vec<String> = Vec::new();
+        LLVMContextSetDiagnosticHandler(ctx, Some(collect_diagnostic),
+                                        &mut messages as *mut _ as *mut libc::c_void);
+        // The module being linked in is destroyed by the linker
+        let failed = LLVMLinkModules2(module.0, other.0);
+        ::std::mem::forget(other);
+        LLVMContextSetDiagnosticHandler(ctx, None, ::std::ptr::null_mut());
+        if failed == LLVMTrue {
+            let message = messages.join("\n");
+            return Err(BuildError::Link {
+                input: input.to_path_buf(),
+                symbol: conflicting_symbol(&message),
+                message,
+            });
+        }
+    }
+    let module = linked.expect("units have at least one input");
+    if opt.debug == DebugInfo::Strip {
+        LLVMStripModuleDebugInfo(module.0);
+    }
+    Ok(module)
+}
+
+/// Rename the symbols of the module as requested by `opt`
+unsafe fn rename_symbols(module: &Module, input: &Path, opt: &BuildOptions)
+-> Result<Vec<(String, String)>, BuildError> {
+    symbols::rename(module, opt).map_err(|c| BuildError::Rename {
+        input: input.to_path_buf(),
+        symbol: c.symbol,
+        renamed: c.renamed,
+        reason: c.reason
+    })
+}
+
+/// Collect the errors reported through the diagnostic handler
+extern "C" fn collect_diagnostic(info: LLVMDiagnosticInfoRef, messages: *mut libc::c_void) {
+    unsafe {
+        if LLVMGetDiagInfoSeverity(info) == LLVMDiagnosticSeverity::Error {
+            let messages = &mut *(messages as *mut Vec<String>);
+            messages.push(take_message(LLVMGetDiagInfoDescription(info)));
         }
-        LLVMContextDispose(ctx);
+    }
+}
+
+/// Extract the name of the symbol out of the message reported by the linker
+///
+/// Conflicts are reported as `Linking globals named 'foo': symbol multiply defined!`.
+fn conflicting_symbol(message: &str) -> Option<String> {
+    let start = message.find("named '").map(|i| i + "named '".len());
+    start.and_then(|start| {
+        message[start..].find('\'').map(|end| String::from(&message[start..start + end]))
+    })
+}
+
+/// Read and parse an input, returning the module along with the source of textual IR
+unsafe fn parse_input(ctx: LLVMContextRef, input: &Input)
+-> Result<(Module, Option<String>), BuildError> {
+    let mut module = ::std::ptr::null_mut();
+    let mut msg = ::std::ptr::null_mut();
+    let name = input.name();
+
+    // Read the LLVM-IR/BC into memory
+    let contents = input.contents().map_err(|e| BuildError::Read {
+        input: name.to_path_buf(),
+        error: e
+    })?;
+    let buffer_name = CString::new(name.to_string_lossy().into_owned()).map_err(|_|
+                      BuildError::InvalidPath {
+                          path: name.to_path_buf(),
+                          reason: "input name contains nulls"
+                      })?;
+    let buf = LLVMCreateMemoryBufferWithMemoryRangeCopy(contents.as_ptr() as *const libc::c_char,
+                                                        contents.len() as libc::size_t,
+                                                        buffer_name.as_ptr());
+
+    // Parse the IR/BC
+    LLVMParseIRInContext(ctx, buf, &mut module, &mut msg);
+    if module.is_null() {
+        let message = take_message(msg);
+        return Err(BuildError::Parse {
+            input: name.to_path_buf(),
+            diagnostic: Box::new(Diagnostic::from_parse_error(name, &message)),
+            message,
+        });
+    }
+    // Keep the source around for diagnostics
+    Ok((Module(module), source_text(&contents)))
+}
+
+/// Verify the module of an input, `source` being the textual IR of the input if available
+unsafe fn verify_input(module: &Module, input: &Path, source: Option<&str>)
+-> Result<(), BuildError> {
+    let mut msg = ::std::ptr::null_mut();
+    if LLVMVerifyModule(module.0, VerifierFailureAction::ReturnStatus, &mut msg) == LLVMTrue {
+        let message = take_message(msg);
+        let function = functions(module.0).find(|&f| {
+            LLVMIsDeclaration(f) == LLVMFalse &&
+            LLVMVerifyFunction(f, VerifierFailureAction::ReturnStatus) == LLVMTrue
+        }).map(|f| value_name(f));
+        return Err(BuildError::Verify {
+            input: input.to_path_buf(),
+            diagnostic: Box::new(Diagnostic::from_verify_error(input, &message, function, source)),
+            message,
+        });
+    }
+    Ok(())
+}
+
+/// Get the contents of the input if it is textual IR
+fn source_text(bytes: &[u8]) -> Option<String> {
+    // Both raw and wrapped bytecode
+    if bytes.starts_with(b"BC\xC0\xDE") || bytes.starts_with(b"\xDE\xC0\x17\x0B") {
+        None
+    } else {
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Create the target machine described by `opt` and set the target of the module
+unsafe fn target_machine(module: &Module, input: &Path, opt: &BuildOptions)
+-> Result<TargetMachine, BuildError> {
+    let mut msg = ::std::ptr::null_mut();
+    let invalid_option = |field| BuildError::InvalidOptions {
+        input: input.to_path_buf(),
+        options: Box::new(opt.clone()),
+        field
+    };
+
+    let triple = CString::new(opt.triple.clone()).map_err(|_| invalid_option("triple"))?;
+    let cpu = CString::new(opt.cpu.clone()).map_err(|_| invalid_option("cpu"))?;
+    let attr = CString::new(opt.attr.clone()).map_err(|_| invalid_option("attr"))?;
+    if !opt.triple.is_empty() {
+        LLVMSetTarget(module.0, triple.as_ptr());
+    }
+    if let Some(backend) = targets::backend_for(&opt.triple) {
+        fail_if!(!available_backends().contains(&backend), BuildError::TargetUnavailable {
+            input: input.to_path_buf(),
+            options: Box::new(opt.clone()),
+            backend
+        });
+    }
+    let mut target = ::std::ptr::null_mut();
+    let status = LLVMGetTargetFromTriple(triple.as_ptr(), &mut target, &mut msg);
+    fail_if!(status != LLVMFalse, BuildError::UnsupportedTarget {
+        input: input.to_path_buf(),
+        options: Box::new(opt.clone()),
+        message: take_message(msg)
+    });
+    let machine = LLVMCreateTargetMachine(target,
+                                          triple.as_ptr(),
+                                          cpu.as_ptr(),
+                                          attr.as_ptr(),
+                                          opt.opt,
+                                          opt.reloc,
+                                          opt.model);
+    fail_if!(machine.is_null(), BuildError::TargetMachine {
+        input: input.to_path_buf(),
+        options: Box::new(opt.clone())
+    });
+    Ok(TargetMachine(machine))
+}
+
+/// The IR optimisation pipeline chosen in `opt`, empty if there is nothing to run
+fn pipeline(opt: &BuildOptions) -> String {
+    let pipeline = match opt.passes {
+        Passes::None => String::new(),
+        Passes::Standard => format!("default<O{}>", opt.opt as u32),
+        Passes::Custom(ref p) => p.clone(),
+    };
+    // Internalised definitions nothing refers to are removed even when not optimising
+    match (opt.exports.is_some(), pipeline.is_empty()) {
+        (true, true) => String::from("globaldce"),
+        (true, false) => format!("{},globaldce", pipeline),
+        (false, _) => pipeline,
+    }
+}
+
+/// Run the IR optimisation pipeline chosen in `opt` on the module
+unsafe fn optimise(machine: &TargetMachine, module: &Module, input: &Path, opt: &BuildOptions)
+-> Result<(), BuildError> {
+    let pipeline = pipeline(opt);
+    if pipeline.is_empty() {
+        return Ok(());
+    }
+    let pipeline = CString::new(pipeline).map_err(|_| BuildError::InvalidOptions {
+        input: input.to_path_buf(),
+        options: Box::new(opt.clone()),
+        field: "passes"
+    })?;
+    let options = LLVMCreatePassBuilderOptions();
+    let err = LLVMRunPasses(module.0, pipeline.as_ptr(), machine.0, options);
+    LLVMDisposePassBuilderOptions(options);
+    fail_if!(!err.is_null(), BuildError::Passes {
+        input: input.to_path_buf(),
+        options: Box::new(opt.clone()),
+        message: take_error(err)
+    });
+    Ok(())
+}
+
+/// The option of `llc` selecting the assembly dialect, if the target supports a choice
+fn assembly_syntax_option(triple: &str, syntax: AssemblySyntax) -> Option<&'static str> {
+    let arch = triple.split('-').next().unwrap_or("");
+    let is_x86 = arch.starts_with("x86") ||
+                 (arch.len() == 4 && arch.starts_with('i') && arch.ends_with("86"));
+    if !is_x86 {
+        return None;
+    }
+    Some(match syntax {
+        AssemblySyntax::Att => "-x86-asm-syntax=att",
+        AssemblySyntax::Intel => "-x86-asm-syntax=intel",
+    })
+}
+
+/// Name of the assembly listing for an input: `<input stem>.<arch>.s`
+fn listing_name(input: &Path, opt: &BuildOptions) -> String {
+    let stem = input.file_stem().map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| String::from("input"));
+    match opt.triple.split('-').next() {
+        Some(arch) if !arch.is_empty() => format!("{}.{}.s", stem, arch),
+        _ => format!("{}.s", stem),
+    }
+}
 
+/// Generate the machine code for a parsed module into the object file at `object`
+unsafe fn emit_object(machine: &TargetMachine, module: &Module, input: &Path, opt: &BuildOptions,
+                      object: &Path)
+-> Result<(), BuildError> {
+    let mut msg = ::std::ptr::null_mut();
+    let object_file = path_to_cstring(object)?;
 
-        Ok(Printout {
-            libname: libstem,
-            outdir: outpath,
-            deps: deps
+    let status = LLVMTargetMachineEmitToFile(machine.0,
+                                             module.0,
+                                             object_file.as_ptr(),
+                                             CodeGenFileType::Object,
+                                             &mut msg);
+    fail_if!(status == LLVMTrue, BuildError::Emit {
+        input: input.to_path_buf(),
+        options: Box::new(opt.clone()),
+        message: take_message(msg)
+    });
+    Ok(())
+}
+
+/// Generate the machine code for a parsed module into memory
+unsafe fn emit_to_memory(machine: &TargetMachine, module: &Module, input: &Path,
+                         opt: &BuildOptions, kind: CodeGenFileType)
+-> Result<MemoryBuffer, BuildError> {
+    let mut msg = ::std::ptr::null_mut();
+    let mut buf = ::std::ptr::null_mut();
+    let status = LLVMTargetMachineEmitToMemoryBuffer(machine.0, module.0, kind, &mut msg, &mut buf);
+    fail_if!(status == LLVMTrue, BuildError::Emit {
+        input: input.to_path_buf(),
+        options: Box::new(opt.clone()),
+        message: take_message(msg)
+    });
+    Ok(MemoryBuffer(buf))
+}
+
+/// Generate the assembly listing of a parsed module in the given syntax
+///
+/// The code generator only supports choosing the syntax through a process-wide option of LLVM,
+/// which may be given once per process and is left alone. Intel syntax listings are disassembled
+/// from the object file instead.
+unsafe fn assembly_listing(machine: &TargetMachine, module: &Module, input: &Path,
+                           opt: &BuildOptions, syntax: AssemblySyntax)
+-> Result<String, BuildError> {
+    if syntax == AssemblySyntax::Att || assembly_syntax_option(&opt.triple, syntax).is_none() {
+        let buf = emit_to_memory(machine, module, input, opt, CodeGenFileType::Assembly)?;
+        return Ok(String::from_utf8_lossy(buf.as_bytes()).into_owned());
+    }
+    let object = emit_to_memory(machine, module, input, opt, CodeGenFileType::Object)?;
+    let string = |s: &str| CString::new(s).expect("checked by target_machine");
+    disasm::intel_listing(&object, &string(&opt.triple), &string(&opt.cpu), &string(&opt.attr))
+        .map_err(|message| BuildError::Emit {
+            input: input.to_path_buf(),
+            options: Box::new(opt.clone()),
+            message
         })
+}
+
+/// Produce the textual assembly for an input
+///
+/// The input must be well formed LLVM-IR or LLVM bytecode. No archive or other files are produced.
+/// `syntax` is only taken into account on x86 targets.
+pub fn assembly<I: Into<Input>>(input: I, opt: &BuildOptions, syntax: AssemblySyntax)
+-> Result<String, BuildError> {
+    initialize_llvm();
+    let input = input.into();
+    unsafe {
+        let ctx = LLVMContextCreate();
+        fail_if!(ctx.is_null(), BuildError::Context);
+        let ctx = Context(ctx);
+        let module = link_inputs(ctx.0, ::std::slice::from_ref(&input), opt, false,
+                                 &mut Vec::new())?;
+        let input = input.name();
+        rename_symbols(&module, input, opt)?;
+        if let Some(ref exports) = opt.exports {
+            symbols::internalise(&module, exports);
+        }
+        let machine = target_machine(&module, input, opt)?;
+        optimise(&machine, &module, input, opt)?;
+        assembly_listing(&machine, &module, input, opt, syntax)
     }
 }
@@ -0,0 +1,78 @@
+//! Archives built for several targets at once
+use std::fmt;
+use std::iter::FromIterator;
+use std::path::PathBuf;
+use std::slice;
+
+use {BuildError, Printout};
+
+/// The archive built for one of the targets of `Build::compile_targets`
+pub struct TargetArchive {
+    /// The target, as given
+    pub target: String,
+    /// Path to the archive, which only exists if it has been built
+    pub archive: PathBuf,
+    /// The outcome of building the archive
+    pub result: Result<Printout, BuildError>,
+}
+
+/// The archives built for several targets by `Build::compile_targets`, in the order the targets
+/// were given
+pub struct TargetArchives {
+    archives: Vec<TargetArchive>,
+}
+
+impl TargetArchives {
+    /// Iterate over the archives of all the targets
+    pub fn iter<'a>(&'a self) -> slice::Iter<'a, TargetArchive> {
+        self.archives.iter()
+    }
+
+    /// Iterate over the archives which have been built
+    pub fn succeeded<'a>(&'a self) -> impl Iterator<Item=&'a TargetArchive> + 'a {
+        self.archives.iter().filter(|a| a.result.is_ok())
+    }
+
+    /// Iterate over the archives which could not be built
+    pub fn failed<'a>(&'a self) -> impl Iterator<Item=&'a TargetArchive> + 'a {
+        self.archives.iter().filter(|a| a.result.is_err())
+    }
+
+    /// Whether the archives of all the targets have been built
+    pub fn all_succeeded(&self) -> bool {
+        self.failed().next().is_none()
+    }
+
+    /// The archive of the target, if it was one of the targets
+    pub fn get(&self, target: &str) -> Option<&TargetArchive> {
+        self.archives.iter().find(|a| a.target == target)
+    }
+}
+
+impl FromIterator<TargetArchive> for TargetArchives {
+    fn from_iter<I: IntoIterator<Item=TargetArchive>>(iter: I) -> TargetArchives {
+        TargetArchives { archives: iter.into_iter().collect() }
+    }
+}
+
+impl<'a> IntoIterator for &'a TargetArchives {
+    type Item = &'a TargetArchive;
+    type IntoIter = slice::Iter<'a, TargetArchive>;
+    fn into_iter(self) -> slice::Iter<'a, TargetArchive> {
+        self.iter()
+    }
+}
+
+impl fmt::Display for TargetArchives {
+    /// One line per target, e.g. `x86_64-unknown-linux-gnu: built target/libfoo.a`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for archive in &self.archives {
+            match archive.result {
+                Ok(_) => writeln!(f, "{}: built {}", archive.target,
+                                  archive.archive.display())?,
+                Err(ref e) => writeln!(f, "{}: failed: {}", archive.target, e)?,
+            }
+        }
+        Ok(())
+    }
+}
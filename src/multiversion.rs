@@ -0,0 +1,221 @@
+//! Variants of an input built for several sets of target features and the dispatchers choosing
+//! between them at runtime
+use std::ffi::CString;
+use std::fmt::Write;
+
+use {link_inputs, with_input, BuildError, BuildOptions, Input, TargetFeatures, Triple};
+use externs::{self, Signature};
+use ffi::*;
+use symbols;
+use targets::initialize_llvm;
+
+/// Features LLVM names differently from `is_x86_feature_detected!`
+const X86_RUST_NAMES: &[(&str, &str)] = &[
+    ("cx16", "cmpxchg16b"),
+    ("pclmul", "pclmulqdq"),
+    ("rdrnd", "rdrand"),
+    ("sahf", "lahfsahf"),
+];
+
+/// Features LLVM names differently from `is_aarch64_feature_detected!`
+const AARCH64_RUST_NAMES: &[(&str, &str)] = &[
+    ("fp-armv8", "fp"),
+    ("fullfp16", "fp16"),
+];
+
+/// An input built once for every set of target features
+#[derive(Clone, Debug)]
+pub struct Multiversion {
+    pub input: Input,
+    /// The sets of features, in the format of `BuildOptions::attr`, from the least to the most
+    /// preferred
+    pub variants: Vec<String>,
+}
+
+/// A set of features the input is built for
+struct Variant {
+    features: String,
+    /// Suffix of the symbols of the variant, e.g. `avx2`
+    suffix: String,
+    /// Rust names of the features to detect before choosing the variant
+    detect: Vec<String>,
+}
+
+impl Multiversion {
+    /// The inputs to build for the variants and the Rust source of the dispatchers
+    ///
+    /// Every variant is built with `defaults` and its own features. The externally visible
+    /// functions of a variant are suffixed with the features, e.g. `foo_avx2`, and dispatched to
+    /// by a function exported under the original name.
+    pub fn expand(&self, defaults: &BuildOptions)
+    -> Result<(Vec<(Input, BuildOptions)>, String), BuildError> {
+        let name = self.input.name();
+        let failed = |variant: &str, reason| BuildError::Multiversion {
+            input: name.to_path_buf(),
+            variant: String::from(variant),
+            reason
+        };
+        let triple = Triple::parse(&defaults.triple).map_err(|e| with_input(e, &self.input))?;
+        let (detect, rust_names) = match triple.arch() {
+            "x86_64" | "i386" | "i486" | "i586" | "i686" =>
+                (Some("::std::is_x86_feature_detected!"), X86_RUST_NAMES),
+            "aarch64" | "arm64" => (Some("::std::arch::is_aarch64_feature_detected!"),
+                                    AARCH64_RUST_NAMES),
+            _ => (None, &[][..]),
+        };
+        if self.variants.is_empty() {
+            return Err(failed("", "there are no variants to build"));
+        }
+        let mut variants: Vec<Variant> = Vec::new();
+        for features in &self.variants {
+            let parsed = features.parse::<TargetFeatures>()
+                                 .map_err(|e| with_input(e, &self.input))?;
+            let detected = parsed.iter().filter(|&(_, e)| e).map(|(f, _)| {
+                let rust = rust_names.iter().find(|&&(l, _)| l == f).map(|&(_, r)| r);
+                String::from(rust.unwrap_or(f))
+            }).collect::<Vec<_>>();
+            if !detected.is_empty() && detect.is_none() {
+                return Err(failed(features, "runtime detection of target features is not \
+                                             supported on the architecture"));
+            }
+            let suffix = parsed.iter().map(|(f, e)| format!("{}{}", if e { "" } else { "no" }, f))
+                               .collect::<Vec<_>>().join("_");
+            let suffix = if suffix.is_empty() { String::from("baseline") } else { suffix };
+            let suffix = suffix.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+            if variants.iter().any(|v| v.suffix == suffix) {
+                return Err(failed(features, "another variant has the same features"));
+            }
+            variants.push(Variant { features: features.clone(), suffix, detect: detected });
+        }
+        // The dispatchers would have no variant to call on CPUs lacking the detected features
+        if variants.iter().all(|v| !v.detect.is_empty()) {
+            return Err(failed("", "one of the variants must not enable any features, to be the \
+                                   fallback for CPUs supporting none of the others"));
+        }
+
+        let signatures = unsafe { signatures(&self.input, defaults)? };
+        // Functions internalised by the export list are not dispatched to
+        let mut dispatched = Vec::new();
+        for signature in signatures.functions {
+            let public = match defaults.symbol_renames.get(&signature.name) {
+                Some(renamed) => renamed.clone(),
+                None => format!("{}{}", defaults.symbol_prefix, signature.name),
+            };
+            if defaults.exports.as_ref().map_or(true, |e| e.contains(&public)) {
+                dispatched.push((signature, public));
+            }
+        }
+
+        let inputs = variants.iter().map(|variant| {
+            let mut opt = defaults.clone();
+            opt.attr = match (defaults.attr.is_empty(), variant.features.is_empty()) {
+                (_, true) => defaults.attr.clone(),
+                (true, false) => variant.features.clone(),
+                (false, false) => format!("{},{}", defaults.attr, variant.features),
+            };
+            for (signature, public) in &dispatched {
+                let renamed = format!("{}_{}", public, variant.suffix);
+                if let Some(ref mut exports) = opt.exports {
+                    exports.remove(public);
+                    exports.insert(renamed.clone());
+                }
+                opt.symbol_renames.insert(signature.name.clone(), renamed);
+            }
+            (self.input.clone(), opt)
+        }).collect();
+
+        let mut shim = format!("// Dispatchers of the functions of {}\n{}",
+                               name.display(), signatures.structs);
+        for (signature, public) in &dispatched {
+            shim.push_str(&dispatcher(signature, public, &variants, detect.unwrap_or("")));
+        }
+        Ok((inputs, shim))
+    }
+}
+
+/// Parse the input to find the signatures of the functions it defines
+unsafe fn signatures(input: &Input, opt: &BuildOptions)
+-> Result<externs::Signatures, BuildError> {
+    let name = input.name();
+    initialize_llvm();
+    let ctx = LLVMContextCreate();
+    if ctx.is_null() {
+        return Err(BuildError::Context);
+    }
+    let ctx = Context(ctx);
+    let module = link_inputs(ctx.0, ::std::slice::from_ref(input), opt, false, &mut Vec::new())?;
+    // Vectors are declared with the types of the target
+    let triple = CString::new(opt.triple.clone()).expect("checked by Triple::parse");
+    LLVMSetTarget(module.0, triple.as_ptr());
+    let shared = global_variables(module.0).chain(global_aliases(module.0))
+                                           .find(|&g| symbols::is_exported(g));
+    if let Some(global) = shared {
+        return Err(BuildError::Multiversion {
+            input: name.to_path_buf(),
+            variant: String::new(),
+            reason: if LLVMIsAGlobalAlias(global).is_null() {
+                "externally visible global variables cannot be multiversioned"
+            } else {
+                "externally visible aliases cannot be multiversioned"
+            }
+        });
+    }
+    let unsupported = |function, reason| BuildError::UnsupportedSignature {
+        input: name.to_path_buf(),
+        function,
+        reason
+    };
+    let signatures = externs::signatures(&module).map_err(|e|
+                     unsupported(e.function, e.reason))?;
+    if let Some(variadic) = signatures.functions.iter().find(|s| s.variadic) {
+        return Err(unsupported(variadic.name.clone(),
+                               String::from("variadic functions cannot be dispatched")));
+    }
+    Ok(signatures)
+}
+
+/// The Rust function exported as `public` which calls the best variant the CPU supports
+///
+/// The variant is chosen on the first call.
+fn dispatcher(signature: &Signature, public: &str, variants: &[Variant], detect: &str)
+-> String {
+    let ident = externs::identifier(public);
+    let types = signature.params.iter().map(|(_, t)| &t[..]).collect::<Vec<_>>();
+    let args = signature.params.iter().map(|(n, _)| &n[..]).collect::<Vec<_>>();
+    let pointer = format!("unsafe extern \"{}\" fn({}){}",
+                          signature.abi, types.join(", "), signature.ret());
+    let mut ret = String::new();
+    let _ = write!(ret, "\n/// Calls the variant of `{}` best supported by the CPU\n\
+                         #[export_name = {:?}]\n\
+                         pub unsafe extern \"{}\" fn {}({}){} {{\n    extern \"{}\" {{\n",
+                   public, public, signature.abi, ident, signature.params(), signature.ret(),
+                   signature.abi);
+    for variant in variants {
+        let symbol = format!("{}_{}", public, variant.suffix);
+        let _ = write!(ret, "        #[link_name = {:?}]\n        fn {}({}){};\n",
+                       symbol, externs::identifier(&symbol), signature.params(),
+                       signature.ret());
+    }
+    let _ = write!(ret, "    }}\n    static RESOLVED: ::std::sync::atomic::AtomicUsize = \
+                         ::std::sync::atomic::AtomicUsize::new(0);\n    \
+                         let mut resolved = RESOLVED.load(::std::sync::atomic::Ordering::Relaxed);\
+                         \n    if resolved == 0 {{\n        resolved = ");
+    // The most preferred variant comes last, the first variant without features to detect is
+    // the fallback, of which `expand` makes sure there is one
+    for variant in variants.iter().rev() {
+        let symbol = externs::identifier(&format!("{}_{}", public, variant.suffix));
+        if variant.detect.is_empty() {
+            let _ = write!(ret, "{{\n            {} as *const () as usize\n        }};\n", symbol);
+            break;
+        }
+        let condition = variant.detect.iter().map(|f| format!("{}({:?})", detect, f))
+                                             .collect::<Vec<_>>().join(" && ");
+        let _ = write!(ret, "if {} {{\n            {} as *const () as usize\n        }} else ",
+                       condition, symbol);
+    }
+    let _ = write!(ret, "        RESOLVED.store(resolved, ::std::sync::atomic::Ordering::Relaxed);\
+                         \n    }}\n    unsafe {{\n        \
+                         let variant: {} = ::std::mem::transmute(resolved);\n        \
+                         variant({})\n    }}\n}}\n", pointer, args.join(", "));
+    ret
+}
@@ -0,0 +1,179 @@
+//! CPUs and features known to a target
+use std::cmp;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use {BuildError, Triple};
+use backend::RUSTC_VERSION;
+use ffi::*;
+use targets::initialize_llvm;
+use tools::tool;
+
+/// A CPU or a feature known to a target
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TargetEntry {
+    /// Name of the CPU or the feature, as accepted by `BuildOptions::cpu` or `BuildOptions::attr`
+    pub name: String,
+    /// Description of the CPU or the feature
+    pub description: String,
+}
+
+/// Everything LLVM knows about the subtargets of a target
+#[derive(Clone, Debug, Default)]
+pub struct Subtargets {
+    pub cpus: Vec<TargetEntry>,
+    pub features: Vec<TargetEntry>,
+}
+
+/// List the CPUs LLVM knows for the target triple
+///
+/// Runs `llc -mtriple=<triple> -mcpu=help`.
+///
+/// The `llc` named by `$LLVM_BUILD_UTILS_LLC`, or else the one in `$PATH`, must be of the same
+/// version as the LLVM in use.
+pub fn target_cpus(triple: &str) -> Result<Vec<TargetEntry>, BuildError> {
+    query(triple).map(|s| s.cpus.clone())
+}
+
+/// List the features LLVM knows for the target triple
+///
+/// Requires `llc`, as does `target_cpus`.
+pub fn target_features(triple: &str) -> Result<Vec<TargetEntry>, BuildError> {
+    query(triple).map(|s| s.features.clone())
+}
+
+/// Query the CPUs and features of the target triple, caching the result
+pub fn query(triple: &str) -> Result<Arc<Subtargets>, BuildError> {
+    type Queried = HashMap<String, Result<Arc<Subtargets>, String>>;
+    static QUERIED: OnceLock<Mutex<Queried>> = OnceLock::new();
+    let triple = Triple::parse(triple)?.to_string();
+    check_available(&triple)?;
+    let queried = QUERIED.get_or_init(|| Mutex::new(HashMap::new()));
+    let cached = queried.lock().unwrap_or_else(|e| e.into_inner()).get(&triple).cloned();
+    let result = match cached {
+        Some(result) => result,
+        None => {
+            let result = print_help(&triple).map(|help| Arc::new(parse_help(&help)));
+            queried.lock().unwrap_or_else(|e| e.into_inner())
+                   .insert(triple.clone(), result.clone());
+            result
+        }
+    };
+    result.map_err(|message| BuildError::QuerySubtargets { triple, message })
+}
+
+/// The candidate closest to `name` in spelling, if any is close enough to be a likely typo
+pub fn closest<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where I: IntoIterator<Item=&'a TargetEntry> {
+    let threshold = cmp::max(1, name.len() / 3);
+    candidates.into_iter()
+              .map(|c| (distance(name, &c.name), &c.name[..]))
+              .filter(|&(d, _)| d <= threshold)
+              .min_by_key(|&(d, _)| d)
+              .map(|(_, c)| c)
+}
+
+/// Levenshtein distance between two strings
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..b.len() + 1).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = cmp::min(substitution, cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Check that the backend of the target is a part of the LLVM in use
+fn check_available(triple: &str) -> Result<(), BuildError> {
+    initialize_llvm();
+    let ctriple = CString::new(triple).expect("valid triples have no null bytes");
+    let mut target = ::std::ptr::null_mut();
+    let mut msg = ::std::ptr::null_mut();
+    unsafe {
+        if LLVMGetTargetFromTriple(ctriple.as_ptr(), &mut target, &mut msg) != LLVMFalse {
+            LLVMDisposeMessage(msg);
+            return Err(BuildError::InvalidTriple {
+                input: None,
+                triple: String::from(triple),
+                reason: "the target is not available in the LLVM in use"
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Capture what `llc` prints when asked for help with `-mcpu=help`
+///
+/// The LLVM this crate is linked to only prints the help to stderr, once per process, so `llc`
+/// is asked instead. It must be of the same version as the LLVM in use, lest it knows of other
+/// CPUs and features.
+fn print_help(triple: &str) -> Result<String, String> {
+    let llc = tool("LLVM_BUILD_UTILS_LLC", "llc");
+    let run = |command: &mut Command| command.stdin(Stdio::null()).output().map_err(|e|
+        format!("could not execute {}: {}; set LLVM_BUILD_UTILS_LLC to the path of llc",
+                llc.display(), e));
+    let expected = llvm_version(RUSTC_VERSION).ok_or_else(||
+        String::from("the version of the LLVM in use is not known"))?;
+    let version = run(Command::new(&llc).arg("--version"))?;
+    let version = String::from_utf8_lossy(&version.stdout);
+    let found = llvm_version(&version).unwrap_or("unknown");
+    if found != expected {
+        return Err(format!("{} is of LLVM {} rather than LLVM {}, which is in use; set \
+                            LLVM_BUILD_UTILS_LLC to the llc of LLVM {}",
+                           llc.display(), found, expected, expected));
+    }
+    let output = run(Command::new(&llc).arg(format!("-mtriple={}", triple))
+                                       .arg("-mcpu=help"))?;
+    if !output.status.success() {
+        return Err(format!("{} failed with {}: {}", llc.display(), output.status,
+                           String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    // Versions of LLVM differ in where the help goes
+    let mut help = String::from_utf8_lossy(&output.stderr).into_owned();
+    help.push_str(&String::from_utf8_lossy(&output.stdout));
+    Ok(help)
+}
+
+/// The version following the first mention of LLVM, e.g. `18.1.7` in `LLVM version 18.1.7`
+fn llvm_version(text: &str) -> Option<&str> {
+    let text = &text[text.find("LLVM").unwrap_or(text.len())..];
+    text.find(|c: char| c.is_ascii_digit()).map(|start| {
+        let text = &text[start..];
+        let end = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+        &text[..end]
+    })
+}
+
+/// Parse the CPU and feature tables out of the help printed by LLVM
+fn parse_help(help: &str) -> Subtargets {
+    let mut subtargets = Subtargets::default();
+    let mut table = None;
+    for line in help.lines() {
+        if line.starts_with("Available CPUs") {
+            table = Some(&mut subtargets.cpus);
+        } else if line.starts_with("Available features") {
+            table = Some(&mut subtargets.features);
+        } else if !line.starts_with("  ") {
+            if !line.is_empty() {
+                table = None;
+            }
+        } else if let Some(ref mut table) = table {
+            let mut parts = line.splitn(2, " - ");
+            let name = parts.next().unwrap_or("").trim();
+            let description = parts.next().unwrap_or("").trim().trim_end_matches('.');
+            table.push(TargetEntry {
+                name: String::from(name),
+                description: String::from(description),
+            });
+        }
+    }
+    subtargets
+}
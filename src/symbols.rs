@@ -0,0 +1,115 @@
+//! Renaming and internalisation of the symbols of a module
+use std::collections::BTreeSet;
+
+use libc;
+
+use BuildOptions;
+use ffi::*;
+
+/// A symbol which cannot be renamed
+pub struct Conflict {
+    pub symbol: String,
+    pub renamed: String,
+    pub reason: &'static str,
+}
+
+/// Rename the symbols of the module as requested by `symbol_prefix` and `symbol_renames`
+///
+/// Returns the renamed definitions as `(original, renamed)` pairs, sorted by the original name, as
+/// the order of the module depends on the version of LLVM that linked it. References to the
+/// renamed symbols within the module follow along.
+pub unsafe fn rename(module: &Module, opt: &BuildOptions)
+-> Result<Vec<(String, String)>, Conflict> {
+    if opt.symbol_prefix.is_empty() && opt.symbol_renames.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut planned = Vec::new();
+    let mut kept = Vec::new();
+    for global in globals(module) {
+        let name = value_name(global);
+        let defined = is_exported(global);
+        let renamed = match opt.symbol_renames.get(&name) {
+            Some(renamed) => renamed.clone(),
+            None if defined && !opt.symbol_prefix.is_empty() && !name.starts_with("llvm.") =>
+                format!("{}{}", opt.symbol_prefix, name),
+            None => {
+                kept.push(name);
+                continue;
+            }
+        };
+        let conflict = |reason| Conflict {
+            symbol: name.clone(),
+            renamed: renamed.clone(),
+            reason
+        };
+        if name.starts_with("llvm.") {
+            return Err(conflict("intrinsics cannot be renamed"));
+        }
+        if renamed.is_empty() {
+            return Err(conflict("the new name is empty"));
+        }
+        if (name.clone() + &renamed).contains(['\0', '\t', '\n']) {
+            return Err(conflict("names containing null bytes, tabs or newlines are not supported"));
+        }
+        planned.push((global, name, renamed, defined));
+    }
+    for (idx, (_, name, renamed, _)) in planned.iter().enumerate() {
+        if kept.contains(renamed) || planned[..idx].iter().any(|p| p.2 == *renamed) {
+            return Err(Conflict {
+                symbol: name.clone(),
+                renamed: renamed.clone(),
+                reason: "another symbol already has this name"
+            });
+        }
+    }
+    // Clear the names first, so that symbols may swap names without LLVM making them unique
+    for &(global, _, _, _) in &planned {
+        LLVMSetValueName2(global, "".as_ptr() as *const libc::c_char, 0);
+    }
+    let mut ret = Vec::new();
+    for (global, name, renamed, defined) in planned {
+        LLVMSetValueName2(global, renamed.as_ptr() as *const libc::c_char,
+                          renamed.len() as libc::size_t);
+        if defined {
+            ret.push((name, renamed));
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+/// Internalise the externally visible definitions not named in `exports`
+///
+/// Returns the names in `exports` the module defines.
+pub unsafe fn internalise(module: &Module, exports: &BTreeSet<String>) -> Vec<String> {
+    let mut ret = Vec::new();
+    for global in globals(module) {
+        if !is_exported(global) {
+            continue;
+        }
+        let name = value_name(global);
+        if exports.contains(&name) {
+            ret.push(name);
+        } else if !name.starts_with("llvm.") {
+            // Local symbols must have the default visibility and cannot be in a COMDAT group
+            LLVMSetLinkage(global, LLVMInternalLinkage);
+            LLVMSetVisibility(global, LLVMDefaultVisibility);
+            LLVMSetComdat(global, ::std::ptr::null_mut());
+        }
+    }
+    ret
+}
+
+/// The functions, global variables and aliases of the module
+unsafe fn globals(module: &Module) -> Vec<LLVMValueRef> {
+    functions(module.0).chain(global_variables(module.0))
+                       .chain(global_aliases(module.0))
+                       .collect()
+}
+
+/// Whether the global is a definition visible outside of the module
+pub unsafe fn is_exported(global: LLVMValueRef) -> bool {
+    let linkage = LLVMGetLinkage(global);
+    LLVMIsDeclaration(global) == LLVMFalse &&
+    linkage != LLVMInternalLinkage && linkage != LLVMPrivateLinkage
+}
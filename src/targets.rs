@@ -0,0 +1,133 @@
+//! Discovery and initialisation of the backends the LLVM in use has been built with
+use std::ffi::{CStr, CString};
+use std::sync::OnceLock;
+
+use libc;
+
+use ffi::*;
+
+/// Backends LLVM may contain, by the name used in their initialisation functions
+const BACKENDS: &[&str] = &[
+    "AArch64", "AMDGPU", "ARC", "ARM", "AVR", "BPF", "CSKY", "Hexagon", "Lanai", "LoongArch",
+    "M68k", "Mips", "MSP430", "NVPTX", "PowerPC", "RISCV", "Sparc", "SPIRV", "SystemZ", "VE",
+    "WebAssembly", "X86", "XCore", "Xtensa",
+];
+
+/// Initialise every backend available in the LLVM this crate is linked to
+///
+/// The backends are looked up at runtime rather than linked to, so that a backend missing from
+/// the LLVM in use results in an error when it is requested rather than a link failure.
+pub fn initialize_llvm() {
+    available_backends();
+}
+
+/// Names of the backends available in the LLVM in use, e.g. `X86` or `RISCV`
+pub fn available_backends() -> &'static [&'static str] {
+    static AVAILABLE: OnceLock<Vec<&'static str>> = OnceLock::new();
+    AVAILABLE.get_or_init(|| {
+        BACKENDS.iter().cloned().filter(|b| unsafe { initialize_backend(b) }).collect()
+    })
+}
+
+/// Names of the targets registered by the available backends, e.g. `x86-64` or `riscv64`
+///
+/// Corresponds to the list of targets printed by `llc --version`.
+pub fn available_targets() -> Vec<String> {
+    initialize_llvm();
+    let mut ret = Vec::new();
+    unsafe {
+        let mut target = LLVMGetFirstTarget();
+        while !target.is_null() {
+            ret.push(CStr::from_ptr(LLVMGetTargetName(target)).to_string_lossy().into_owned());
+            target = LLVMGetNextTarget(target);
+        }
+    }
+    ret
+}
+
+/// The backend responsible for the architecture of the target triple, if it is known
+pub fn backend_for(triple: &str) -> Option<&'static str> {
+    let arch = triple.split('-').next().unwrap_or("");
+    let prefixes: &[(&str, &'static str)] = &[
+        ("x86", "X86"), ("i386", "X86"), ("i486", "X86"), ("i586", "X86"), ("i686", "X86"),
+        ("aarch64", "AArch64"), ("arm64", "AArch64"), ("arm", "ARM"), ("thumb", "ARM"),
+        ("mips", "Mips"), ("powerpc", "PowerPC"), ("ppc", "PowerPC"), ("riscv", "RISCV"),
+        ("wasm", "WebAssembly"), ("s390x", "SystemZ"), ("systemz", "SystemZ"),
+        ("sparc", "Sparc"), ("loongarch", "LoongArch"), ("hexagon", "Hexagon"),
+        ("msp430", "MSP430"), ("bpf", "BPF"), ("avr", "AVR"), ("nvptx", "NVPTX"),
+        ("amdgcn", "AMDGPU"), ("r600", "AMDGPU"), ("m68k", "M68k"), ("csky", "CSKY"),
+        ("xtensa", "Xtensa"), ("lanai", "Lanai"), ("ve", "VE"), ("xcore", "XCore"),
+        ("spirv", "SPIRV"), ("arc", "ARC"),
+    ];
+    prefixes.iter().find(|&&(prefix, _)| arch.starts_with(prefix)).map(|&(_, backend)| backend)
+}
+
+/// Initialise a single backend, returning whether it is available
+unsafe fn initialize_backend(backend: &str) -> bool {
+    let lookup = |component: &str| lookup(&format!("LLVMInitialize{}{}", backend, component));
+    let required = [lookup("TargetInfo"), lookup("Target"), lookup("TargetMC")];
+    if required.iter().any(|f| f.is_none()) {
+        return false;
+    }
+    // Backends without an assembly parser, printer or disassembler are still usable to some extent
+    let optional = [lookup("AsmPrinter"), lookup("AsmParser"), lookup("Disassembler")];
+    for init in required.iter().chain(optional.iter()).filter_map(|f| *f) {
+        init();
+    }
+    true
+}
+
+/// Look up an initialisation function in the LLVM this crate is linked to
+#[cfg(unix)]
+unsafe fn lookup(name: &str) -> Option<extern "C" fn()> {
+    let name = CString::new(name).expect("names of initialisation functions have no nulls");
+    let sym = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr());
+    if sym.is_null() {
+        None
+    } else {
+        Some(::std::mem::transmute::<*mut libc::c_void, extern "C" fn()>(sym))
+    }
+}
+
+/// Look up an initialisation function in the LLVM this crate is linked to
+///
+/// There is no portable way to look up the functions at runtime here, so only the backends which
+/// have always been a part of rustc’s LLVM are supported.
+#[cfg(not(unix))]
+unsafe fn lookup(name: &str) -> Option<extern "C" fn()> {
+    macro_rules! known(
+        ($($method:ident),*) => { {
+            extern { $(fn $method();)* }
+            match name {
+                $(stringify!($method) => Some($method as extern "C" fn()),)*
+                _ => None
+            }
+        } }
+    );
+    known!(LLVMInitializeX86TargetInfo,
+           LLVMInitializeX86Target,
+           LLVMInitializeX86TargetMC,
+           LLVMInitializeX86AsmPrinter,
+           LLVMInitializeX86AsmParser,
+           LLVMInitializeX86Disassembler,
+           LLVMInitializeARMTargetInfo,
+           LLVMInitializeARMTarget,
+           LLVMInitializeARMTargetMC,
+           LLVMInitializeARMAsmPrinter,
+           LLVMInitializeARMAsmParser,
+           LLVMInitializeAArch64TargetInfo,
+           LLVMInitializeAArch64Target,
+           LLVMInitializeAArch64TargetMC,
+           LLVMInitializeAArch64AsmPrinter,
+           LLVMInitializeAArch64AsmParser,
+           LLVMInitializeMipsTargetInfo,
+           LLVMInitializeMipsTarget,
+           LLVMInitializeMipsTargetMC,
+           LLVMInitializeMipsAsmPrinter,
+           LLVMInitializeMipsAsmParser,
+           LLVMInitializePowerPCTargetInfo,
+           LLVMInitializePowerPCTarget,
+           LLVMInitializePowerPCTargetMC,
+           LLVMInitializePowerPCAsmPrinter,
+           LLVMInitializePowerPCAsmParser)
+}
@@ -0,0 +1,348 @@
+//! Code generation by the executables of an LLVM installation
+use std::env;
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use {assembly_syntax_option, pipeline, ArchiveKind, ArchiveMember, Backend, BuildError,
+     BuildOptions, CodegenModel, DebugInfo, Diagnostic, Emitted, Input, Parsed, Relocations};
+use mktemp;
+
+/// A backend driving the `opt`, `llc` and `llvm-ar` executables of an LLVM installation
+///
+/// Useful where the LLVM this crate is linked to cannot generate code, e.g. when it lacks a
+/// target. Inputs are converted to bytecode and optimised by `opt`, compiled by `llc` and
+/// archived by `llvm-ar`, or by any executables taking the same arguments.
+///
+/// The tools are unable to link inputs together (see `Build::link`), rename or internalise
+/// symbols, generate line tables or declare the functions in Rust (see `Build::extern_decls`).
+/// Building with any of these fails with `BuildError::Unsupported`. Writing COFF archives needs
+/// the `llvm-ar` of LLVM 15 or newer.
+#[derive(Clone, Debug)]
+pub struct ExternalTools {
+    opt: PathBuf,
+    llc: PathBuf,
+    ar: PathBuf,
+}
+
+/// An input converted to bytecode by `ExternalTools`
+struct Converted {
+    tools: ExternalTools,
+    input: PathBuf,
+    opt: BuildOptions,
+    /// Directory the tools are run in, holding the input and the bytecode
+    dir: mktemp::Temp,
+    /// Name of the bytecode in `dir`
+    bitcode: String,
+}
+
+/// Why running a tool failed
+enum Failure {
+    /// The tool could not be executed
+    Spawn(String),
+    /// The tool reported an error
+    Tool(String),
+}
+
+impl ExternalTools {
+    /// Use the given `opt`, `llc` and `llvm-ar` executables
+    pub fn new<P, Q, R>(opt: P, llc: Q, ar: R) -> ExternalTools
+    where P: Into<PathBuf>, Q: Into<PathBuf>, R: Into<PathBuf> {
+        ExternalTools { opt: opt.into(), llc: llc.into(), ar: ar.into() }
+    }
+
+    /// Use the executables named by `$LLVM_BUILD_UTILS_OPT`, `$LLVM_BUILD_UTILS_LLC` and
+    /// `$LLVM_BUILD_UTILS_AR`, or else `opt`, `llc` and `llvm-ar` in `$PATH`
+    pub fn from_env() -> ExternalTools {
+        ExternalTools::new(tool("LLVM_BUILD_UTILS_OPT", "opt"),
+                           tool("LLVM_BUILD_UTILS_LLC", "llc"),
+                           tool("LLVM_BUILD_UTILS_AR", "llvm-ar"))
+    }
+}
+
+impl Default for ExternalTools {
+    fn default() -> ExternalTools {
+        ExternalTools::from_env()
+    }
+}
+
+impl Backend for ExternalTools {
+    fn version(&self) -> Result<String, BuildError> {
+        // `llvm-ar` has no bearing on the objects
+        let versions = [&self.opt, &self.llc].iter().map(|tool| {
+            let version = run(Command::new(tool).arg("--version")).map_err(|e|
+                          BuildError::Version { tool: tool.to_path_buf(), message: e.message() })?;
+            Ok(format!("{} ({})", String::from_utf8_lossy(&version).trim(), tool.display()))
+        }).collect::<Result<Vec<_>, BuildError>>()?;
+        Ok(versions.join("; "))
+    }
+
+    fn parse(&self, inputs: &[Input], opt: &BuildOptions, _: bool)
+    -> Result<Box<dyn Parsed>, BuildError> {
+        let input = inputs[0].name();
+        let unsupported = |feature| Err(BuildError::Unsupported {
+            input: input.to_path_buf(),
+            feature
+        });
+        if inputs.len() > 1 {
+            return unsupported("linking inputs together");
+        } else if !opt.symbol_prefix.is_empty() || !opt.symbol_renames.is_empty() {
+            return unsupported("renaming symbols");
+        } else if opt.exports.is_some() {
+            return unsupported("internalising symbols");
+        } else if opt.debug == DebugInfo::LineTables {
+            return unsupported("generating line tables");
+        }
+        let contents = inputs[0].contents().map_err(|e| BuildError::Read {
+            input: input.to_path_buf(),
+            error: e
+        })?;
+        // The tools are run in a directory of their own on a copy of the input, so that the
+        // object does not depend on the directory the input is in
+        let temp_error = |e| BuildError::TempFile { input: input.to_path_buf(), error: e };
+        let dir = mktemp::Temp::new_dir().map_err(&temp_error)?;
+        let file_name = input.file_name().map(|n| n.to_string_lossy().into_owned())
+                             .unwrap_or_else(|| String::from("input"));
+        fs::write(dir.as_ref().join(&file_name), &contents).map_err(&temp_error)?;
+        let bitcode = format!("{}.bc", file_name);
+        let mut command = Command::new(&self.opt);
+        command.current_dir(dir.as_ref()).args(["-disable-verify", "-o", &bitcode, &file_name]);
+        match run(&mut command) {
+            Ok(_) => {}
+            Err(Failure::Tool(message)) => {
+                // Messages are prefixed with the name of the tool
+                let start = message.find(&format!("{}:", file_name)).unwrap_or(0);
+                let message = String::from(&message[start..]);
+                return Err(BuildError::Parse {
+                    input: input.to_path_buf(),
+                    diagnostic: Box::new(Diagnostic::from_parse_error(input, &message)),
+                    message,
+                });
+            }
+            Err(Failure::Spawn(message)) => return Err(BuildError::Emit {
+                input: input.to_path_buf(),
+                options: Box::new(opt.clone()),
+                message
+            }),
+        }
+        Ok(Box::new(Converted {
+            tools: self.clone(),
+            input: input.to_path_buf(),
+            opt: opt.clone(),
+            dir,
+            bitcode,
+        }))
+    }
+
+    fn write_archive(&self, archive: &Path, kind: ArchiveKind, members: &[(&CStr, ArchiveMember)])
+    -> Result<(), BuildError> {
+        let write_error = |message: String| BuildError::WriteArchive {
+            archive: archive.to_path_buf(),
+            message
+        };
+        let io_error = |e: io::Error| write_error(e.to_string());
+        let dir = mktemp::Temp::new_dir().map_err(&io_error)?;
+        let mut files = Vec::new();
+        for (idx, &(name, member)) in members.iter().enumerate() {
+            let name = name.to_str().map_err(|_|
+                       write_error(String::from("member name is not utf-8")))?;
+            // The tool names members after their files, so every member is staged in a
+            // directory of its own
+            let staged = dir.as_ref().join(idx.to_string());
+            fs::create_dir(&staged).map_err(&io_error)?;
+            match member {
+                ArchiveMember::Object(path) => {
+                    fs::hard_link(path, staged.join(name))
+                        .or_else(|_| fs::copy(path, staged.join(name)).map(|_| ()))
+                        .map_err(&io_error)?;
+                }
+                ArchiveMember::Existing(existing, idx) => {
+                    let read_error = |message: String| BuildError::ReadArchive {
+                        archive: existing.path.clone(),
+                        message
+                    };
+                    let names = &existing.members;
+                    let original = names.get(idx).ok_or_else(|| read_error(
+                                   format!("the archive has no member at index {}", idx)))?;
+                    // Members of the same name are told apart by their count
+                    let count = names[..idx + 1].iter().filter(|n| n.0 == original.0).count();
+                    let original = original.0.to_string_lossy();
+                    let original = &original[..];
+                    let mut command = Command::new(&self.ar);
+                    command.current_dir(&staged).arg("xN").arg(count.to_string())
+                           .arg(absolute(&existing.path)).arg(original);
+                    run(&mut command).map_err(|e| read_error(e.message()))?;
+                    if original != name {
+                        fs::rename(staged.join(original), staged.join(name))
+                            .map_err(&io_error)?;
+                    }
+                }
+            }
+            files.push(staged.join(name));
+        }
+        match fs::remove_file(archive) {
+            Err(ref e) if e.kind() != io::ErrorKind::NotFound =>
+                return Err(write_error(e.to_string())),
+            _ => {}
+        }
+        let format = match kind {
+            ArchiveKind::Gnu | ArchiveKind::Mips64 => "gnu",
+            ArchiveKind::Bsd => "bsd",
+            ArchiveKind::Coff => "coff",
+        };
+        let mut command = Command::new(&self.ar);
+        command.arg(format!("--format={}", format)).arg("qcs").arg(archive).args(&files);
+        run(&mut command).map(|_| ()).map_err(|e| write_error(e.message()))
+    }
+}
+
+impl Parsed for Converted {
+    fn verify(&self) -> Result<(), BuildError> {
+        let mut command = Command::new(&self.tools.opt);
+        command.current_dir(self.dir.as_ref())
+               .args(["-passes=verify", "-disable-output", &self.bitcode]);
+        match run(&mut command) {
+            Ok(_) => Ok(()),
+            Err(Failure::Tool(message)) => {
+                // The verifier reports the problems, followed by the complaint of the tool
+                let complaint = format!("{}:", self.bitcode);
+                let message = message.lines().filter(|l| !l.contains(&complaint))
+                                     .collect::<Vec<_>>().join("\n");
+                Err(BuildError::Verify {
+                    input: self.input.clone(),
+                    diagnostic: Box::new(Diagnostic::from_verify_error(&self.input, &message,
+                                                                       None, None)),
+                    message,
+                })
+            }
+            Err(Failure::Spawn(message)) => Err(self.emit_error(message)),
+        }
+    }
+
+    fn emit_object(self: Box<Self>, object: &Path, listing: Option<&Path>, externs: bool)
+    -> Result<Emitted, BuildError> {
+        if externs {
+            return Err(BuildError::Unsupported {
+                input: self.input.clone(),
+                feature: "declaring functions in Rust"
+            });
+        }
+        let pipeline = pipeline(&self.opt);
+        let mut bitcode = self.bitcode.clone();
+        if !pipeline.is_empty() || self.opt.debug == DebugInfo::Strip {
+            let optimised = format!("optimised.{}", bitcode);
+            let mut command = Command::new(&self.tools.opt);
+            command.current_dir(self.dir.as_ref());
+            if self.opt.debug == DebugInfo::Strip {
+                command.arg("-strip-debug");
+            }
+            if !pipeline.is_empty() {
+                command.arg(format!("-passes={}", pipeline));
+            }
+            command.args(["-o", &optimised, &bitcode]);
+            run(&mut command).map_err(|e| match e {
+                Failure::Tool(message) => BuildError::Passes {
+                    input: self.input.clone(),
+                    options: Box::new(self.opt.clone()),
+                    message
+                },
+                Failure::Spawn(message) => self.emit_error(message),
+            })?;
+            bitcode = optimised;
+        }
+        run(&mut self.llc("obj", object, &bitcode)).map_err(|e|
+            self.emit_error(e.message()))?;
+        if let Some(listing) = listing {
+            let mut command = self.llc("asm", listing, &bitcode);
+            if let Some(syntax) = self.opt.asm_listing {
+                command.args(assembly_syntax_option(&self.opt.triple, syntax));
+            }
+            run(&mut command).map_err(|e| self.emit_error(e.message()))?;
+        }
+        Ok(Emitted::default())
+    }
+}
+
+impl Converted {
+    /// `llc` generating the file of the type from the bytecode, as `opt` asks for
+    fn llc(&self, filetype: &str, output: &Path, bitcode: &str) -> Command {
+        let opt = &self.opt;
+        let mut command = Command::new(&self.tools.llc);
+        command.current_dir(self.dir.as_ref())
+               .arg(format!("-filetype={}", filetype))
+               .arg(format!("-O{}", opt.opt as u32));
+        for &(name, ref value) in &[("mtriple", &opt.triple), ("mcpu", &opt.cpu),
+                                    ("mattr", &opt.attr)] {
+            if !value.is_empty() {
+                command.arg(format!("-{}={}", name, value));
+            }
+        }
+        let reloc = match opt.reloc {
+            Relocations::Default => None,
+            Relocations::Static => Some("static"),
+            Relocations::PIC => Some("pic"),
+            Relocations::DynamicNoPic => Some("dynamic-no-pic"),
+        };
+        if let Some(reloc) = reloc {
+            command.arg(format!("-relocation-model={}", reloc));
+        }
+        let model = match opt.model {
+            CodegenModel::Default => None,
+            CodegenModel::Small => Some("small"),
+            CodegenModel::Kernel => Some("kernel"),
+            CodegenModel::Medium => Some("medium"),
+            CodegenModel::Large => Some("large"),
+        };
+        if let Some(model) = model {
+            command.arg(format!("-code-model={}", model));
+        }
+        command.arg("-o").arg(absolute(output)).arg(bitcode);
+        command
+    }
+
+    fn emit_error(&self, message: String) -> BuildError {
+        BuildError::Emit {
+            input: self.input.clone(),
+            options: Box::new(self.opt.clone()),
+            message
+        }
+    }
+}
+
+impl Failure {
+    fn message(self) -> String {
+        match self {
+            Failure::Spawn(message) | Failure::Tool(message) => message,
+        }
+    }
+}
+
+/// Run the tool, returning what it printed to the standard output
+fn run(command: &mut Command) -> Result<Vec<u8>, Failure> {
+    let output = command.output().map_err(|e|
+                 Failure::Spawn(format!("could not execute {:?}: {}", command, e)))?;
+    if output.status.success() {
+        return Ok(output.stdout);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(Failure::Tool(if stderr.trim().is_empty() {
+        format!("{:?} failed with {}", command, output.status)
+    } else {
+        String::from(stderr.trim_end())
+    }))
+}
+
+/// The executable named by `$<var>`, or else `default` in `$PATH`
+pub fn tool(var: &str, default: &str) -> PathBuf {
+    env::var_os(var).map(PathBuf::from).unwrap_or_else(|| PathBuf::from(default))
+}
+
+/// The path, relative to the current directory rather than the one the tools are run in
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    env::current_dir().map(|d| d.join(path)).unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,156 @@
+//! Target triples, as understood by LLVM
+use std::ffi::CString;
+use std::fmt;
+use std::str::FromStr;
+
+use {take_message, BuildError};
+use ffi::*;
+use targets;
+
+/// Architecture families where the architecture name is followed by a sub-architecture
+const SUBARCH_FAMILIES: &[&str] = &["armeb", "arm", "thumbeb", "thumb"];
+
+/// A target triple
+///
+/// The target triple has the general format `<arch><sub>-<vendor>-<sys>-<abi>` (see
+/// `BuildOptions::triple`). Missing components are filled in the same way LLVM does, so that
+/// `x86_64-linux-gnu` becomes `x86_64-unknown-linux-gnu`.
+///
+/// ```rust,no_run
+/// extern crate llvm_build_utils;
+/// use llvm_build_utils::*;
+///
+/// fn main() {
+///     let triple = Triple::from_rust_target("riscv64gc-unknown-linux-gnu").unwrap();
+///     assert_eq!(triple.arch(), "riscv64");
+///     assert_eq!(triple.to_string(), "riscv64-unknown-linux-gnu");
+/// }
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Triple {
+    arch: String,
+    sub: String,
+    vendor: String,
+    sys: String,
+    abi: String,
+}
+
+impl Triple {
+    /// Parse and normalise an LLVM target triple
+    pub fn parse(triple: &str) -> Result<Triple, BuildError> {
+        let invalid = |reason| BuildError::InvalidTriple {
+            input: None,
+            triple: String::from(triple),
+            reason
+        };
+        if triple.is_empty() {
+            return Err(invalid("the triple is empty"));
+        }
+        if triple.contains(|c: char| c.is_whitespace() || c == '\0') {
+            return Err(invalid("the triple contains whitespace or null bytes"));
+        }
+        let normalised = normalise(triple);
+        let mut components = normalised.splitn(4, '-');
+        let arch = components.next().unwrap_or("");
+        if arch.is_empty() || targets::backend_for(arch).is_none() {
+            return Err(invalid("the architecture is not known"));
+        }
+        let (arch, sub) = split_arch(arch);
+        Ok(Triple {
+            arch: String::from(arch),
+            sub: String::from(sub),
+            vendor: String::from(components.next().unwrap_or("unknown")),
+            sys: String::from(components.next().unwrap_or("unknown")),
+            abi: String::from(components.next().unwrap_or("")),
+        })
+    }
+
+    /// Convert the name of a Rust target (such as `$TARGET` in build scripts) to the triple rustc
+    /// passes to LLVM for that target
+    ///
+    /// For example `riscv64gc-unknown-linux-gnu` becomes `riscv64-unknown-linux-gnu` and
+    /// `aarch64-apple-darwin` becomes `arm64-apple-macosx`.
+    pub fn from_rust_target(target: &str) -> Result<Triple, BuildError> {
+        let components = target.split('-').collect::<Vec<_>>();
+        let (arch, rest) = (components[0], components[1..].join("-"));
+        let llvm = match (arch, &rest[..]) {
+            (_, "apple-darwin") => format!("{}-apple-macosx", apple_arch(arch)),
+            (_, "apple-ios-sim") | ("x86_64", "apple-ios") =>
+                format!("{}-apple-ios-simulator", apple_arch(arch)),
+            _ if components.len() > 1 && components[1] == "apple" =>
+                format!("{}-{}", apple_arch(arch), rest),
+            ("armv7", "linux-androideabi") => String::from("armv7-none-linux-android"),
+            (_, "pc-windows-gnullvm") => format!("{}-pc-windows-gnu", arch),
+            _ if arch.starts_with("riscv32") || arch.starts_with("riscv64") =>
+                format!("{}-{}", &arch[..7], rest),
+            _ => String::from(target),
+        };
+        Triple::parse(&llvm)
+    }
+
+    /// The architecture, without the sub-architecture, e.g. `x86_64`, `arm` or `riscv64`
+    pub fn arch(&self) -> &str {
+        &self.arch
+    }
+
+    /// The sub-architecture, e.g. `v7a` or `v6m` on ARM, empty if there is none
+    pub fn sub(&self) -> &str {
+        &self.sub
+    }
+
+    /// The vendor, e.g. `pc`, `apple` or `unknown`
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+
+    /// The operating system, e.g. `linux`, `windows` or `none`
+    pub fn sys(&self) -> &str {
+        &self.sys
+    }
+
+    /// The ABI or environment, e.g. `gnu`, `musl` or `eabihf`, empty if there is none
+    pub fn abi(&self) -> &str {
+        &self.abi
+    }
+}
+
+impl fmt::Display for Triple {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}-{}-{}", self.arch, self.sub, self.vendor, self.sys)?;
+        if !self.abi.is_empty() {
+            write!(f, "-{}", self.abi)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Triple {
+    type Err = BuildError;
+    fn from_str(s: &str) -> Result<Triple, BuildError> {
+        Triple::parse(s)
+    }
+}
+
+/// Normalise the triple the way LLVM does
+fn normalise(triple: &str) -> String {
+    let triple = CString::new(triple).expect("null bytes are rejected before normalising");
+    unsafe { take_message(LLVMNormalizeTargetTriple(triple.as_ptr())) }
+}
+
+/// Split the architecture into the architecture proper and the sub-architecture
+fn split_arch(arch: &str) -> (&str, &str) {
+    if arch.starts_with("arm64") {
+        return (arch, "");
+    }
+    for family in SUBARCH_FAMILIES {
+        if arch.starts_with(family) {
+            return arch.split_at(family.len());
+        }
+    }
+    (arch, "")
+}
+
+/// Apple platforms call AArch64 `arm64`
+fn apple_arch(arch: &str) -> &str {
+    if arch == "aarch64" { "arm64" } else { arch }
+}
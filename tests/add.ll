@@ -0,0 +1,4 @@
+define i32 @add(i32 %a, i32 %b) {
+    %r = add i32 %a, %b
+    ret i32 %r
+}
@@ -1,6 +1,17 @@
 extern crate llvm_build_utils;
 
 use llvm_build_utils::*;
+use std::error::Error;
+use std::path::Path;
+
+/// Whether the LLVM in use accepts the opaque `ptr` type, as LLVM 15 and later do
+fn opaque_pointers() -> bool {
+    // e.g. `...; LLVM version: 19.1.7` or `LLVM 14.0.6 (/usr/bin/llvm-config)`
+    let version = InProcess.version().unwrap();
+    let llvm = version.rsplit("LLVM").next().unwrap().trim_start_matches(" version:");
+    let major = llvm.trim_start().split('.').next().unwrap();
+    major.parse::<u32>().unwrap() >= 15
+}
 
 #[test]
 fn test_build() {
@@ -41,11 +52,11 @@ fn allow_dynamic_dispatch() {
     use std::path::*;
     let pb = PathBuf::from("libtest.a");
     let t1 = Path::new("tests/test.ll");
-    build_archive_kind(ArchiveKind::Gnu, &pb as &AsRef<Path>,
-    &[(&t1 as &AsRef<Path>, BuildOptions {
+    build_archive_kind(ArchiveKind::Gnu, &pb as &dyn AsRef<Path>,
+    &[(&t1 as &dyn AsRef<Path>, BuildOptions {
         triple: String::from("x86_64-unknown-linux-gnu"),
         ..BuildOptions::default()
-    }), (&"tests/test.ll" as &AsRef<Path>, BuildOptions {
+    }), (&"tests/test.ll" as &dyn AsRef<Path>, BuildOptions {
         triple: String::from("i386-unknown-linux-gnu"),
         ..BuildOptions::default()
     })]).unwrap().print();
@@ -100,3 +111,743 @@ fn test_wrong_things_fail_5() {
         ..BuildOptions::default()
     })]).err().unwrap());
 }
+
+#[test]
+fn test_error_kinds() {
+    match build_archive_kind(ArchiveKind::Gnu, "libfail.a", &[("tests/does_not_exist_for_sure.ll",
+                             BuildOptions::default())]) {
+        Err(ref e @ BuildError::Read { .. }) => {
+            assert_eq!(e.input(), Some(Path::new("tests/does_not_exist_for_sure.ll")));
+            assert!(e.source().is_some());
+        }
+        _ => panic!("expected a read error"),
+    }
+    match build_archive_kind(ArchiveKind::Gnu, "test.a", &[("tests/test.ll",
+                             BuildOptions::default())]) {
+        Err(BuildError::ArchiveName { .. }) => {}
+        _ => panic!("expected an archive name error"),
+    }
+    let err = build_archive_kind(ArchiveKind::Gnu, "libfail.a", &[("tests/test.ll", BuildOptions {
+        triple: String::from("some weird triple this is"),
+        ..BuildOptions::default()
+    })]).err().unwrap();
+    match err {
+        BuildError::InvalidTriple { ref triple, .. } =>
+            assert_eq!(triple, "some weird triple this is"),
+        _ => panic!("expected an invalid triple error"),
+    }
+    assert_eq!(err.input(), Some(Path::new("tests/test.ll")));
+}
+
+#[test]
+fn test_triple() {
+    let triple = Triple::parse("armv7-unknown-linux-gnueabihf").unwrap();
+    assert_eq!(triple.arch(), "arm");
+    assert_eq!(triple.sub(), "v7");
+    assert_eq!(triple.vendor(), "unknown");
+    assert_eq!(triple.sys(), "linux");
+    assert_eq!(triple.abi(), "gnueabihf");
+    assert_eq!(Triple::parse("x86_64-linux-gnu").unwrap().to_string(), "x86_64-unknown-linux-gnu");
+    assert!(Triple::parse("some weird triple this is").is_err());
+    assert!("frobnicator-unknown-linux-gnu".parse::<Triple>().is_err());
+
+    let rust = |t| Triple::from_rust_target(t).unwrap().to_string();
+    assert_eq!(rust("riscv64gc-unknown-linux-gnu"), "riscv64-unknown-linux-gnu");
+    assert_eq!(rust("aarch64-apple-darwin"), "arm64-apple-macosx");
+    assert_eq!(rust("x86_64-pc-windows-msvc"), "x86_64-pc-windows-msvc");
+    assert_eq!(rust("aarch64-apple-ios-sim"), "arm64-apple-ios-simulator");
+    assert_eq!(rust("x86_64-apple-ios"), "x86_64-apple-ios-simulator");
+    assert_eq!(rust("aarch64-pc-windows-gnullvm"), "aarch64-pc-windows-gnu");
+}
+
+#[test]
+fn test_backends() {
+    assert!(available_backends().contains(&"X86"));
+    assert!(available_targets().iter().any(|t| t == "x86-64"));
+    for triple in &["riscv64-unknown-linux-gnu", "wasm32-unknown-unknown",
+                    "s390x-unknown-linux-gnu", "sparcv9-sun-solaris",
+                    "loongarch64-unknown-linux-gnu", "bpfel-unknown-none"] {
+        // Code generation failures abort the process, so build something every backend can lower
+        let opt = BuildOptions { triple: String::from(*triple), ..BuildOptions::default() };
+        match build_archive_kind(ArchiveKind::Gnu, "libbackend.a", &[("tests/add.ll", opt)]) {
+            Ok(_) | Err(BuildError::TargetUnavailable { .. }) => {}
+            Err(e) => panic!("{}: {}", triple, e),
+        }
+    }
+}
+
+#[test]
+fn test_subtargets() {
+    let triple = "x86_64-unknown-linux-gnu";
+    assert!(target_cpus(triple).unwrap().iter().any(|c| c.name == "skylake"));
+    assert!(target_features(triple).unwrap().iter().any(|f| f.name == "avx2"));
+    assert!(target_cpus("some weird triple this is").is_err());
+
+    let opt = BuildOptions { triple: String::from(triple), cpu: String::from("skylak"),
+                             ..BuildOptions::default() };
+    match build_archive_kind(ArchiveKind::Gnu, "libfail.a", &[("tests/test.ll", opt)]) {
+        Err(BuildError::Unrecognised { field: "cpu", ref suggestion, .. }) =>
+            assert_eq!(suggestion.as_ref().map(|s| &s[..]), Some("skylake")),
+        _ => panic!("expected an unrecognised CPU error"),
+    }
+    let opt = BuildOptions { triple: String::from(triple), attr: String::from("+sse2,+frobnicate"),
+                             ..BuildOptions::default() };
+    match build_archive_kind(ArchiveKind::Gnu, "libfail.a", &[("tests/test.ll", opt)]) {
+        Err(BuildError::Unrecognised { field: "attr", ref name, .. }) =>
+            assert_eq!(name, "frobnicate"),
+        _ => panic!("expected an unrecognised feature error"),
+    }
+}
+
+#[test]
+fn test_target_features() {
+    let mut features = TargetFeatures::new();
+    features.enable("avx2").disable("fma").enable("avx2");
+    assert_eq!(features.to_string(), "+avx2,-fma");
+    assert_eq!(features.get("fma"), Some(false));
+    assert_eq!(features.get("sse"), None);
+    assert_eq!("+avx2,-fma".parse::<TargetFeatures>().unwrap(), features);
+    assert!("avx2".parse::<TargetFeatures>().is_err());
+
+    let expanded = features.expand("x86_64-unknown-linux-gnu", "x86-64").unwrap();
+    for f in &["avx", "sse4.2", "sse", "cmov"] {
+        assert_eq!(expanded.get(f), Some(true), "{}", f);
+    }
+    assert_eq!(expanded.get("fma"), Some(false));
+    assert!(features.clone().disable("sse").expand("x86_64-unknown-linux-gnu", "").is_err());
+
+    features.disable("avx2");
+    assert_eq!(features.conflicts(), vec!["avx2"]);
+    let opt = BuildOptions { attr: features.to_string(), ..BuildOptions::default() };
+    match build_archive_kind(ArchiveKind::Gnu, "libfail.a", &[("tests/test.ll", opt)]) {
+        Err(BuildError::InvalidFeatures { ref feature, .. }) => assert_eq!(feature, "avx2"),
+        _ => panic!("expected an invalid features error"),
+    }
+}
+
+#[test]
+fn test_parse_diagnostic() {
+    let err = build_archive_kind(ArchiveKind::Gnu, "libfail.a", &[("tests/invalid_parse.ll",
+                                 BuildOptions::default())]).err().unwrap();
+    println!("{}", err);
+    let diag = err.diagnostic().expect("parse errors have a diagnostic");
+    assert_eq!(diag.input, Path::new("tests/invalid_parse.ll"));
+    assert_eq!(diag.line, Some(2));
+    assert_eq!(diag.column, Some(5));
+    assert_eq!(diag.source_line.as_ref().map(|s| &s[..]), Some("    frobnicate i64 %a"));
+}
+
+#[test]
+fn test_verify_diagnostic() {
+    let err = build_archive_kind(ArchiveKind::Gnu, "libfail.a", &[("tests/invalid_verify.ll",
+                                 BuildOptions::default())]).err().unwrap();
+    match err {
+        BuildError::Verify { .. } => {}
+        _ => panic!("expected a verifier error, got {}", err),
+    }
+    let diag = err.diagnostic().expect("verifier errors have a diagnostic");
+    assert_eq!(diag.function.as_ref().map(|s| &s[..]), Some("invalid"));
+    assert_eq!(diag.message, "Instruction does not dominate all uses!");
+    // The use of `%c` before its definition
+    assert_eq!(diag.line, Some(6));
+    assert_eq!(diag.column, Some(5));
+    assert_eq!(diag.source_line.as_ref().map(|s| &s[..]), Some("    %b = add i64 %c, 1"));
+}
+
+#[test]
+fn test_asm_listing() {
+    let printout = build_archive_kind(ArchiveKind::Gnu, "librandasm.a", &[("tests/rdrand.ll",
+                                      BuildOptions {
+        triple: String::from("x86_64-unknown-linux-gnu"),
+        attr: String::from("+rdrnd"),
+        asm_listing: Some(AssemblySyntax::Intel),
+        ..BuildOptions::default()
+    })]).unwrap();
+    assert_eq!(printout.asm_listings().len(), 1);
+    assert!(printout.asm_listings()[0].ends_with("rdrand.x86_64.s"));
+    printout.print();
+}
+
+#[test]
+fn test_assembly_text() {
+    let asm = assembly("tests/test.ll", &BuildOptions {
+        triple: String::from("x86_64-unknown-linux-gnu"),
+        ..BuildOptions::default()
+    }, AssemblySyntax::Att).unwrap();
+    assert!(asm.contains("test:"));
+    assert!(asm.contains("mulq"));
+}
+
+#[test]
+fn test_assembly_syntax() {
+    let options = BuildOptions {
+        triple: String::from("x86_64-unknown-linux-gnu"),
+        ..BuildOptions::default()
+    };
+    // Either syntax may be asked for any number of times
+    for _ in 0..2 {
+        let intel = assembly("tests/link_main.ll", &options, AssemblySyntax::Intel).unwrap();
+        assert!(intel.contains("link_main:\n\tpush\trax\n"), "{}", intel);
+        assert!(intel.contains("# link_helper"));
+        let att = assembly("tests/link_main.ll", &options, AssemblySyntax::Att).unwrap();
+        assert!(att.contains("pushq\t%rax"), "{}", att);
+    }
+}
+
+#[test]
+fn test_builder() {
+    let out_dir = Path::new("target/test_builder");
+    std::fs::create_dir_all(out_dir).unwrap();
+    let printout = Build::new()
+        .file("tests/rdrand.ll")
+        .file_with("tests/rdseed.ll", BuildOptions {
+            triple: String::from("x86_64-unknown-linux-gnu"),
+            cpu: String::from("x86-64"),
+            attr: String::from("+rdseed"),
+            ..BuildOptions::default()
+        })
+        .triple("x86_64-unknown-linux-gnu")
+        .cpu("x86-64")
+        .attr("+rdrnd")
+        .out_dir(out_dir)
+        .try_compile("builder").unwrap();
+    printout.print();
+    assert!(out_dir.join("libbuilder.a").exists());
+}
+
+#[test]
+fn test_extern_decls() {
+    std::fs::create_dir_all("target/test_externs").unwrap();
+    let ptr = if opaque_pointers() { "ptr" } else { "i8*" };
+    let printout = Build::new()
+        .file("tests/rdrand.ll")
+        .input(Input::memory("sigs.ll", format!("
+            define signext i8 @neg(i8 signext %x) {{ %r = sub i8 0, %x\n ret i8 %r }}
+            define {{i64, i64}} @pair({} %p, double %d) {{ ret {{i64, i64}} zeroinitializer }}
+            define internal void @hidden() {{ ret void }}
+        ", ptr)))
+        .triple("x86_64-unknown-linux-gnu")
+        .attr("+rdrnd")
+        .out_dir("target/test_externs")
+        .extern_decls(true)
+        .cargo_metadata(false)
+        .try_compile("externs").unwrap();
+    let decls = std::fs::read_to_string(printout.extern_decls().unwrap()).unwrap();
+    println!("{}", decls);
+    assert!(decls.contains("pub fn librdrand_rust_rand_64() -> u64;"));
+    assert!(decls.contains("pub fn neg(x: i8) -> i8;"));
+    assert!(decls.contains("pub struct pair_ret(pub u64, pub u64);"));
+    assert!(decls.contains("pub fn pair(p: *mut ::std::os::raw::c_void, d: f64) -> pair_ret;"));
+    assert!(!decls.contains("hidden"));
+
+    let err = Build::new()
+        .input(Input::memory("unsupported.ll", "define i24 @odd() { ret i24 0 }"))
+        .triple("x86_64-unknown-linux-gnu")
+        .out_dir("target/test_externs")
+        .extern_decls(true)
+        .try_compile("unsupported").err().unwrap();
+    match err {
+        BuildError::UnsupportedSignature { ref function, .. } => assert_eq!(function, "odd"),
+        _ => panic!("expected an unsupported signature error, got {}", err),
+    }
+}
+
+#[test]
+fn test_archives() {
+    let out_dir = Path::new("target/test_archives");
+    std::fs::create_dir_all(out_dir).unwrap();
+    let mut build = Build::new();
+    build.triple("x86_64-unknown-linux-gnu").attr("+rdrnd").out_dir(out_dir).cargo_metadata(false);
+    build.clone().files(["tests/test.ll", "tests/rdrand.ll"]).compile("vendor");
+    let vendor = out_dir.join("libvendor.a");
+    assert_eq!(archive_members(&vendor).unwrap().len(), 2);
+    assert!(archive_members(out_dir.join("libdoesnotexist.a")).is_err());
+
+    build.clone().archive(&vendor).file("tests/test.ll").compile("appended");
+    assert_eq!(archive_members(out_dir.join("libappended.a")).unwrap().len(), 3);
+    build.clone().archive(&vendor).archive(&vendor).compile("merged");
+    assert_eq!(archive_members(out_dir.join("libmerged.a")).unwrap().len(), 4);
+    build.clone().archive(&vendor).archive(&vendor).replace_members(true).compile("replaced");
+    assert_eq!(archive_members(out_dir.join("libreplaced.a")).unwrap(),
+               archive_members(&vendor).unwrap());
+}
+
+#[test]
+fn test_archive_index() {
+    let out_dir = Path::new("target/test_archive_index");
+    let archives = Build::new()
+        .file("tests/test.ll")
+        .out_dir(out_dir)
+        .compile_targets("index", ["x86_64-unknown-linux-gnu", "x86_64-pc-windows-msvc",
+                                    "x86_64-apple-darwin"]);
+    for (archive, symbol) in archives.iter().zip(&["test", "test", "_test"]) {
+        let contents = std::fs::read(&archive.archive).unwrap();
+        assert!(contents.starts_with(b"!<arch>\n"));
+        // The symbol table is the first member
+        let size = std::str::from_utf8(&contents[56..66]).unwrap().trim().parse::<usize>().unwrap();
+        let index = &contents[68..68 + size];
+        let name = format!("{}\0", symbol);
+        assert!(index.windows(name.len()).any(|w| w == name.as_bytes()), "{}", archive.target);
+    }
+}
+
+#[test]
+fn test_deterministic() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    let hash = |out_dir: &str| {
+        std::fs::create_dir_all(out_dir).unwrap();
+        let printout = Build::new()
+            .files(["tests/test.ll", "tests/rdrand.ll"])
+            .input(Input::memory("test.ll", std::fs::read("tests/test.ll").unwrap()))
+            .triple("x86_64-unknown-linux-gnu")
+            .attr("+rdrnd")
+            .out_dir(out_dir)
+            .jobs(3)
+            .cache(false)
+            .deterministic(true)
+            .cargo_metadata(false)
+            .try_compile("deterministic").unwrap();
+        assert_eq!(printout.cache_hits(), 0);
+        let mut hasher = DefaultHasher::new();
+        std::fs::read(Path::new(out_dir).join("libdeterministic.a")).unwrap().hash(&mut hasher);
+        hasher.finish()
+    };
+    assert_eq!(hash("target/test_deterministic_1"), hash("target/test_deterministic_2"));
+    assert_eq!(archive_members("target/test_deterministic_1/libdeterministic.a").unwrap(),
+               vec!["test.x86_64.o", "rdrand.x86_64.o", "test.x86_64.1.o"]);
+}
+
+#[test]
+fn test_member_names() {
+    let out_dir = Path::new("target/test_member_names");
+    std::fs::create_dir_all(out_dir).unwrap();
+    let named = |name: &str| BuildOptions {
+        triple: String::from("x86_64-unknown-linux-gnu"),
+        ar_section_name: String::from(name),
+        ..BuildOptions::default()
+    };
+    Build::new()
+        .file("tests/test.ll")
+        .file("tests/test.ll")
+        .file_with("tests/test.ll", named("custom.o"))
+        .file_with("tests/test.ll", BuildOptions {
+            triple: String::from("i386-unknown-linux-gnu"),
+            ..BuildOptions::default()
+        })
+        .triple("x86_64-unknown-linux-gnu")
+        .out_dir(out_dir)
+        .cargo_metadata(false)
+        .compile("names");
+    assert_eq!(archive_members(out_dir.join("libnames.a")).unwrap(),
+               vec!["test.x86_64.o", "test.x86_64.1.o", "custom.o", "test.i386.o"]);
+
+    let long = "m".repeat(256);
+    let bad = [("custom.o", "duplicate"), ("a/b.o", "separator"), (&long[..], "length")];
+    for (name, reason) in bad {
+        match Build::new()
+            .file_with("tests/test.ll", named("custom.o"))
+            .file_with("tests/test.ll", named(name))
+            .out_dir(out_dir)
+            .try_compile("badnames") {
+            Err(BuildError::MemberName { name: ref n, .. }) => assert_eq!(n, name, "{}", reason),
+            _ => panic!("expected a member name error for {}", reason),
+        }
+    }
+    let ir = std::fs::read("tests/test.ll").unwrap();
+    match Build::new()
+        .input(Input::memory(format!("{}.ll", "m".repeat(250)), ir))
+        .triple("x86_64-unknown-linux-gnu")
+        .out_dir(out_dir)
+        .try_compile("longnames") {
+        Err(BuildError::MemberName { ref name, .. }) =>
+            assert_eq!(name, &format!("{}.x86_64.o", "m".repeat(250))),
+        _ => panic!("expected a member name error for the default name"),
+    }
+}
+
+#[test]
+fn test_builder_naming() {
+    let out_dir = Path::new("target/test_builder_naming");
+    std::fs::create_dir_all(out_dir).unwrap();
+    Build::new()
+        .file("tests/test.ll")
+        .triple("x86_64-pc-windows-msvc")
+        .out_dir(out_dir)
+        .cargo_metadata(false)
+        .compile("libbuildernaming.a");
+    assert!(out_dir.join("buildernaming.lib").exists());
+}
+
+#[test]
+fn test_parallel() {
+    Build::new()
+        .files(["tests/test.ll", "tests/rdrand.ll", "tests/rdseed.ll", "tests/test.bc"])
+        .triple("x86_64-unknown-linux-gnu")
+        .cpu("x86-64")
+        .attr("+rdrnd,+rdseed")
+        .jobs(4)
+        .compile("parallel");
+}
+
+#[test]
+fn test_parallel_error_order() {
+    let err = Build::new()
+        .files(["tests/test.ll", "tests/does_not_exist_for_sure.ll", "tests/invalid_parse.ll"])
+        .triple("x86_64-unknown-linux-gnu")
+        .jobs(3)
+        .try_compile("parallelfail").err().unwrap();
+    assert_eq!(err.input(), Some(Path::new("tests/does_not_exist_for_sure.ll")));
+}
+
+#[test]
+fn test_cache() {
+    let out_dir = Path::new("target/test_cache");
+    // Start from an empty cache, so that nothing is cached by the previous runs of the test
+    let _ = std::fs::remove_dir_all(out_dir);
+    std::fs::create_dir_all(out_dir).unwrap();
+    let mut build = Build::new();
+    build.files(["tests/test.ll", "tests/rdrand.ll"])
+         .triple("x86_64-unknown-linux-gnu")
+         .attr("+rdrnd")
+         .out_dir(out_dir);
+    build.try_compile("cached").unwrap();
+    assert_eq!(build.try_compile("cached").unwrap().cache_hits(), 2);
+    // The name of the input ends up in the object
+    let renamed = Build::new()
+        .input(Input::memory("renamed.ll", std::fs::read("tests/test.ll").unwrap()))
+        .triple("x86_64-unknown-linux-gnu")
+        .attr("+rdrnd")
+        .out_dir(out_dir)
+        .try_compile("cachedrenamed").unwrap();
+    assert_eq!(renamed.cache_hits(), 0);
+    assert_eq!(build.cache(false).try_compile("cached").unwrap().cache_hits(), 0);
+}
+
+#[test]
+fn test_ir_passes() {
+    let options = |opt, passes| BuildOptions {
+        triple: String::from("x86_64-unknown-linux-gnu"),
+        opt,
+        passes,
+        ..BuildOptions::default()
+    };
+    let asm = assembly("tests/inline.ll", &options(Optimisation::O0, Passes::None),
+                       AssemblySyntax::Att).unwrap();
+    assert!(asm.contains("helper"));
+    let asm = assembly("tests/inline.ll", &options(Optimisation::O2, Passes::Standard),
+                       AssemblySyntax::Att).unwrap();
+    assert!(!asm.contains("helper"));
+    let custom = Passes::Custom(String::from("cgscc(inline),globaldce"));
+    let asm = assembly("tests/inline.ll", &options(Optimisation::O0, custom),
+                       AssemblySyntax::Att).unwrap();
+    assert!(!asm.contains("helper"));
+    match assembly("tests/inline.ll",
+                   &options(Optimisation::O0, Passes::Custom(String::from("no-such-pass"))),
+                   AssemblySyntax::Att) {
+        Err(BuildError::Passes { .. }) => {}
+        _ => panic!("expected a pass pipeline error"),
+    }
+}
+
+#[test]
+fn test_link() {
+    Build::new()
+        .files(["tests/link_main.ll", "tests/link_helper.ll"])
+        .triple("x86_64-unknown-linux-gnu")
+        .link(true)
+        .compile("linked");
+}
+
+#[test]
+fn test_link_conflict() {
+    let err = Build::new()
+        .files(["tests/link_main.ll", "tests/link_helper.ll", "tests/link_conflict.ll"])
+        .triple("x86_64-unknown-linux-gnu")
+        .link(true)
+        .try_compile("linkconflict").err().unwrap();
+    match err {
+        BuildError::Link { ref input, ref symbol, .. } => {
+            assert_eq!(input, Path::new("tests/link_conflict.ll"));
+            assert_eq!(symbol.as_ref().map(|s| &s[..]), Some("link_helper"));
+        }
+        _ => panic!("expected a link error"),
+    }
+}
+
+#[test]
+fn test_symbol_renames() {
+    // Both inputs define `link_helper`, the prefixes keep them apart
+    let prefixed = |prefix: &str| BuildOptions {
+        triple: String::from("x86_64-unknown-linux-gnu"),
+        symbol_prefix: String::from(prefix),
+        ..BuildOptions::default()
+    };
+    let printout = Build::new()
+        .file_with("tests/link_helper.ll", prefixed("first_"))
+        .file_with("tests/link_conflict.ll", prefixed("second_"))
+        .cargo_metadata(false)
+        .try_compile("prefixed").unwrap();
+    assert_eq!(printout.renamed_symbols(),
+               &[(String::from("link_helper"), String::from("first_link_helper")),
+                 (String::from("link_helper"), String::from("second_link_helper"))]);
+
+    let printout = Build::new()
+        .files(["tests/link_main.ll", "tests/link_helper.ll"])
+        .triple("x86_64-unknown-linux-gnu")
+        .link(true)
+        .symbol_prefix("p_")
+        .rename_symbol("link_main", "entry")
+        .cargo_metadata(false)
+        .try_compile("renamed").unwrap();
+    assert_eq!(printout.renamed_symbols(),
+               &[(String::from("link_helper"), String::from("p_link_helper")),
+                 (String::from("link_main"), String::from("entry"))]);
+
+    let mut options = prefixed("");
+    options.symbol_renames.insert(String::from("link_main"), String::from("link_helper"));
+    match assembly("tests/link_main.ll", &options, AssemblySyntax::Att) {
+        Err(BuildError::Rename { ref symbol, .. }) => assert_eq!(symbol, "link_main"),
+        _ => panic!("expected a rename error"),
+    }
+}
+
+#[test]
+fn test_exports() {
+    let options = BuildOptions {
+        triple: String::from("x86_64-unknown-linux-gnu"),
+        passes: Passes::None,
+        exports: Some(vec![String::from("link_main")].into_iter().collect()),
+        ..BuildOptions::default()
+    };
+    let asm = assembly("tests/link_local.ll", &options, AssemblySyntax::Att).unwrap();
+    assert!(asm.lines().any(|l| l == "\t.globl\tlink_main"));
+    // The helper is still defined, but local to the object
+    assert!(asm.lines().any(|l| l == "link_helper:"));
+    assert!(!asm.lines().any(|l| l == "\t.globl\tlink_helper"));
+
+    let mut build = Build::new();
+    build.files(["tests/link_main.ll", "tests/link_helper.ll"])
+         .triple("x86_64-unknown-linux-gnu")
+         .export("link_main")
+         .cargo_metadata(false);
+    build.clone().link(true).compile("exports");
+    // Another input may define the exported symbol
+    build.clone().export("link_helper").compile("exportsunlinked");
+    match build.export("link_missing").try_compile("exportsmissing") {
+        Err(BuildError::UndefinedExport { ref symbol, .. }) => assert_eq!(symbol, "link_missing"),
+        _ => panic!("expected an undefined export error"),
+    }
+}
+
+#[test]
+fn test_line_tables() {
+    let options = |debug| BuildOptions {
+        triple: String::from("x86_64-unknown-linux-gnu"),
+        attr: String::from("+rdrnd"),
+        debug,
+        ..BuildOptions::default()
+    };
+    let asm = assembly("tests/rdrand.ll", &options(DebugInfo::LineTables),
+                       AssemblySyntax::Att).unwrap();
+    // `br label %body` on line 6, indented by 4
+    assert!(asm.lines().any(|l| l.trim_start().starts_with(".loc") && l.contains(" 6 5")));
+    let asm = assembly("tests/rdrand.ll", &options(DebugInfo::Strip),
+                       AssemblySyntax::Att).unwrap();
+    assert!(!asm.contains(".loc"));
+    Build::new()
+        .files(["tests/rdrand.ll", "tests/test.bc"])
+        .triple("x86_64-unknown-linux-gnu")
+        .attr("+rdrnd")
+        .debug(DebugInfo::LineTables)
+        .link(true)
+        .compile("linetables");
+    // LLVM replaces the call of the intrinsic with several instructions while parsing
+    std::fs::create_dir_all("target/test_line_tables").unwrap();
+    let printout = Build::new()
+        .input(Input::memory("upgraded.ll", "
+            declare <2 x i64> @llvm.x86.sse2.pmulu.dq(<4 x i32>, <4 x i32>)
+            define <2 x i64> @upgraded(<4 x i32> %a, <4 x i32> %b) {
+                %r = call <2 x i64> @llvm.x86.sse2.pmulu.dq(<4 x i32> %a, <4 x i32> %b)
+                ret <2 x i64> %r
+            }
+        "))
+        .triple("x86_64-unknown-linux-gnu")
+        .debug(DebugInfo::LineTables)
+        .out_dir("target/test_line_tables")
+        .cargo_metadata(false)
+        .try_compile("upgraded").unwrap();
+    assert!(printout.warnings().iter().any(|w| w.contains("`upgraded` in upgraded.ll")));
+}
+
+#[test]
+fn test_multiversion() {
+    let out_dir = Path::new("target/test_multiversion");
+    std::fs::create_dir_all(out_dir).unwrap();
+    match Build::new()
+        .multiversion("tests/multiversion.ll", ["", "+v"])
+        .triple("riscv64-unknown-linux-gnu")
+        .out_dir(out_dir)
+        .try_compile("multiversionriscv") {
+        Err(BuildError::Multiversion { ref variant, .. }) => assert_eq!(variant, "+v"),
+        _ => panic!("expected a multiversioning error"),
+    }
+    match Build::new()
+        .multiversion("tests/multiversion.ll", ["+avx2", "+avx512f"])
+        .triple("x86_64-unknown-linux-gnu")
+        .out_dir(out_dir)
+        .try_compile("multiversionnofallback") {
+        Err(BuildError::Multiversion { ref variant, .. }) => assert_eq!(variant, ""),
+        _ => panic!("expected a multiversioning error"),
+    }
+
+    // The input is written with opaque pointers
+    if !opaque_pointers() {
+        return;
+    }
+    let printout = Build::new()
+        .multiversion("tests/multiversion.ll", ["", "+avx2", "+avx512f"])
+        .triple("x86_64-unknown-linux-gnu")
+        .out_dir(out_dir)
+        .cargo_metadata(false)
+        .try_compile("multiversion").unwrap();
+    assert_eq!(archive_members(out_dir.join("libmultiversion.a")).unwrap().len(), 3);
+    assert!(printout.renamed_symbols().contains(&(String::from("mv_sum"),
+                                                  String::from("mv_sum_avx2"))));
+    let dispatcher = std::fs::read_to_string(printout.dispatcher().unwrap()).unwrap();
+    println!("{}", dispatcher);
+    assert!(dispatcher.contains("#[export_name = \"mv_sum\"]"));
+    assert!(dispatcher.contains("if ::std::is_x86_feature_detected!(\"avx512f\") {"));
+    assert!(dispatcher.contains("mv_sum_baseline as *const () as usize"));
+}
+
+#[test]
+fn test_compile_targets() {
+    let out_dir = Path::new("target/test_matrix");
+    let archives = Build::new()
+        .file("tests/test.ll")
+        .out_dir(out_dir)
+        .compile_targets("matrix", ["x86_64-unknown-linux-gnu", "x86_64-pc-windows-msvc",
+                                     "x86_64-apple-darwin", "not-a-target"]);
+    println!("{}", archives);
+    assert_eq!(archives.succeeded().count(), 3);
+    assert_eq!(archives.failed().map(|a| &a.target[..]).collect::<Vec<_>>(), ["not-a-target"]);
+    let msvc = archives.get("x86_64-pc-windows-msvc").unwrap();
+    assert_eq!(msvc.archive, out_dir.join("x86_64-pc-windows-msvc/matrix.lib"));
+    assert!(msvc.archive.exists());
+    assert!(out_dir.join("x86_64-apple-darwin/libmatrix.a").exists());
+    assert!(out_dir.join("x86_64-unknown-linux-gnu/libmatrix.a").exists());
+    // A file is in the way of the directory
+    let archives = Build::new().file("tests/test.ll").out_dir("tests/test.ll")
+                               .compile_targets("matrix", ["x86_64-unknown-linux-gnu"]);
+    match archives.get("x86_64-unknown-linux-gnu").unwrap().result {
+        Err(BuildError::OutputDir { ref path, .. }) =>
+            assert_eq!(path, Path::new("tests/test.ll/x86_64-unknown-linux-gnu")),
+        _ => panic!("expected an output directory error"),
+    }
+}
+
+#[test]
+fn test_memory_input() {
+    let ir = (0..4).map(|i| format!("define i32 @generated_{0}() {{\n    ret i32 {0}\n}}\n", i))
+                   .collect::<String>();
+    Build::new()
+        .input(Input::memory("generated.ll", ir))
+        .input(Input::read("test.bc", std::fs::File::open("tests/test.bc").unwrap()).unwrap())
+        .triple("x86_64-unknown-linux-gnu")
+        .compile("memory");
+}
+
+#[test]
+fn test_memory_input_diagnostic() {
+    let err = Build::new()
+        .input(Input::memory("broken.ll", "define void @broken() {\n    rett void\n}\n"))
+        .triple("x86_64-unknown-linux-gnu")
+        .try_compile("memoryfail").err().unwrap();
+    let diag = err.diagnostic().unwrap();
+    assert_eq!(diag.input, Path::new("broken.ll"));
+    assert_eq!(diag.line, Some(2));
+}
+
+/// A backend writing the names of the inputs instead of machine code
+#[derive(Debug)]
+struct NamesBackend;
+
+struct Names(Vec<String>);
+
+impl Backend for NamesBackend {
+    fn version(&self) -> Result<String, BuildError> {
+        Ok(String::from("names"))
+    }
+
+    fn parse(&self, inputs: &[Input], _: &BuildOptions, _: bool)
+    -> Result<Box<dyn Parsed>, BuildError> {
+        Ok(Box::new(Names(inputs.iter().map(|i| i.name().display().to_string()).collect())))
+    }
+
+    fn write_archive(&self, archive: &Path, _: ArchiveKind,
+                     members: &[(&std::ffi::CStr, ArchiveMember)])
+    -> Result<(), BuildError> {
+        let mut contents = String::new();
+        for &(name, member) in members {
+            if let ArchiveMember::Object(path) = member {
+                contents.push_str(&format!("{}: {}\n", name.to_str().unwrap(),
+                                           std::fs::read_to_string(path).unwrap()));
+            }
+        }
+        std::fs::write(archive, contents).unwrap();
+        Ok(())
+    }
+}
+
+impl Parsed for Names {
+    fn verify(&self) -> Result<(), BuildError> {
+        Ok(())
+    }
+
+    fn emit_object(self: Box<Self>, object: &Path, _: Option<&Path>, _: bool)
+    -> Result<Emitted, BuildError> {
+        std::fs::write(object, self.0.join(",")).unwrap();
+        Ok(Emitted::default())
+    }
+}
+
+#[test]
+fn test_custom_backend() {
+    let out_dir = Path::new("target/test_backend");
+    std::fs::create_dir_all(out_dir).unwrap();
+    Build::new()
+        .files(["tests/test.ll", "tests/rdrand.ll", "tests/test.bc"])
+        .triple("x86_64-unknown-linux-gnu")
+        .out_dir(out_dir)
+        .cache(false)
+        .backend(NamesBackend)
+        .compile("names");
+    let archive = std::fs::read_to_string(out_dir.join("libnames.a")).unwrap();
+    assert_eq!(archive, "test.x86_64.o: tests/test.ll\nrdrand.x86_64.o: tests/rdrand.ll\n\
+                         test.x86_64.1.o: tests/test.bc\n");
+}
+
+#[test]
+fn test_external_tools_unsupported() {
+    let err = Build::new()
+        .file("tests/test.ll")
+        .triple("x86_64-unknown-linux-gnu")
+        .symbol_prefix("external_")
+        .backend(ExternalTools::new("opt", "llc", "llvm-ar"))
+        .try_compile("external").err().unwrap();
+    match err {
+        BuildError::Unsupported { feature, .. } => assert_eq!(feature, "renaming symbols"),
+        e => panic!("unexpected error: {}", e),
+    }
+    // The version keys the cache, so it must be known
+    let err = Build::new()
+        .file("tests/test.ll")
+        .backend(ExternalTools::new("tests/does_not_exist_for_sure", "llc", "llvm-ar"))
+        .try_compile("externalversion").err().unwrap();
+    match err {
+        BuildError::Version { ref tool, .. } =>
+            assert_eq!(tool, Path::new("tests/does_not_exist_for_sure")),
+        e => panic!("unexpected error: {}", e),
+    }
+}
@@ -0,0 +1,9 @@
+define internal i64 @helper(i64 %a) {
+    %r = mul i64 %a, 3
+    ret i64 %r
+}
+
+define i64 @inline_me(i64 %a) {
+    %r = call i64 @helper(i64 %a)
+    ret i64 %r
+}
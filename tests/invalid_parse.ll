@@ -0,0 +1,4 @@
+define i64 @frobnicate(i64 %a) {
+    frobnicate i64 %a
+    ret i64 %a
+}
@@ -0,0 +1,9 @@
+define i64 @valid(i64 %a) {
+    ret i64 %a
+}
+
+define i64 @invalid(i64 %a) {
+    %b = add i64 %c, 1
+    %c = add i64 %a, 1
+    ret i64 %b
+}
@@ -0,0 +1,4 @@
+define i64 @link_helper(i64 %a) {
+    %r = add i64 %a, 24
+    ret i64 %r
+}
@@ -0,0 +1,9 @@
+define i64 @link_helper(i64 %a) {
+    %r = add i64 %a, 42
+    ret i64 %r
+}
+
+define i64 @link_main(i64 %a) {
+    %r = call i64 @link_helper(i64 %a)
+    ret i64 %r
+}
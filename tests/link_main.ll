@@ -0,0 +1,6 @@
+declare i64 @link_helper(i64)
+
+define i64 @link_main(i64 %a) {
+    %r = call i64 @link_helper(i64 %a)
+    ret i64 %r
+}
@@ -0,0 +1,17 @@
+define i64 @mv_sum(ptr %p, i64 %n) {
+entry:
+    %empty = icmp eq i64 %n, 0
+    br i1 %empty, label %done, label %loop
+loop:
+    %i = phi i64 [ 0, %entry ], [ %next, %loop ]
+    %acc = phi i64 [ 0, %entry ], [ %sum, %loop ]
+    %addr = getelementptr i64, ptr %p, i64 %i
+    %x = load i64, ptr %addr
+    %sum = add i64 %acc, %x
+    %next = add i64 %i, 1
+    %end = icmp eq i64 %next, %n
+    br i1 %end, label %done, label %loop
+done:
+    %r = phi i64 [ 0, %entry ], [ %sum, %loop ]
+    ret i64 %r
+}